@@ -0,0 +1,318 @@
+/// ActiveRuns.  Tracks the runs in progress for each job key: the pid of the running process and
+/// cancelled and paused flags, so a new run can tell whether the previous one is still going and
+/// an operator can stop, pause or resume it.
+///
+/// a run is registered with a RunGuard that removes it when dropped, at the end of the run.  a run
+/// that waits, for its turn or for a permit, is registered too so it can be cancelled while it waits.
+use hashbrown::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::task::AbortHandle;
+
+#[derive(Debug, Clone, Default)]
+pub struct ActiveRuns {
+    inner: Arc<Mutex<Inner>>,
+    finished: Arc<Notify>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: u64,
+    runs: HashMap<String, Vec<RunHandle>>,
+}
+
+/// RunHandle - a single run in progress
+#[derive(Debug, Clone)]
+pub struct RunHandle {
+    pub id: u64,
+    pid: Arc<AtomicU64>,
+    cancelled: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    step: Arc<AtomicU8>,
+    waiting: Arc<Mutex<Option<AbortHandle>>>,
+}
+
+/// RunGuard - keeps the run registered until dropped
+#[derive(Debug)]
+pub struct RunGuard {
+    key: String,
+    handle: RunHandle,
+    runs: ActiveRuns,
+}
+
+impl RunHandle {
+    /// the pid of the running process; None between attempts or before it starts
+    pub fn pid(&self) -> Option<u64> {
+        Some(self.pid.load(Ordering::SeqCst)).filter(|pid| *pid > 0)
+    }
+
+    /// record the pid of the running process
+    pub fn set_pid(&self, pid: Option<u64>) {
+        self.pid.store(pid.unwrap_or_default(), Ordering::SeqCst);
+    }
+
+    /// flag the run as cancelled and end any wait; the caller stops the process
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(waiting) = self.waiting.lock().unwrap().take() {
+            waiting.abort();
+        }
+    }
+
+    /// return true if the run was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// flag the run as paused or resumed; the caller signals the process
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    /// return true if the run is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// record the step the run last reported
+    pub fn set_step(&self, step: u8) {
+        self.step.store(step, Ordering::SeqCst);
+    }
+
+    /// the step the run last reported, 0 until it reports progress
+    pub fn step(&self) -> u8 {
+        self.step.load(Ordering::SeqCst)
+    }
+
+    /// wait for the future unless the run is cancelled first; None if it was cancelled
+    pub async fn unless_cancelled<T, F>(&self, future: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+    {
+        let task = tokio::spawn(future);
+        *self.waiting.lock().unwrap() = Some(task.abort_handle());
+
+        // checked after the abort handle is set, so a cancel can't slip in between
+        if self.is_cancelled() {
+            task.abort();
+        }
+
+        let result = task.await.ok();
+        self.waiting.lock().unwrap().take();
+
+        result.filter(|_| !self.is_cancelled())
+    }
+}
+
+impl RunGuard {
+    /// the handle of the registered run
+    pub fn handle(&self) -> &RunHandle {
+        &self.handle
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.runs.remove(&self.key, self.handle.id);
+    }
+}
+
+impl ActiveRuns {
+    /// create an empty registry
+    pub fn new() -> ActiveRuns {
+        ActiveRuns::default()
+    }
+
+    /// register a run for the job, whether or not another is active
+    pub fn start(&self, key: &str) -> RunGuard {
+        self.register(key, true)
+            .expect("a forced registration always succeeds")
+    }
+
+    /// register a run for the job only if no other run is active
+    pub fn try_start(&self, key: &str) -> Option<RunGuard> {
+        self.register(key, false)
+    }
+
+    /// wait until the job's active runs finish, then register a run
+    pub async fn wait_start(&self, key: &str) -> RunGuard {
+        loop {
+            // created before the check so a run that finishes in between still wakes us
+            let finished = self.finished.notified();
+            if let Some(guard) = self.try_start(key) {
+                return guard;
+            }
+
+            finished.await;
+        }
+    }
+
+    /// wait until the runs registered for the job before the run with this id have finished
+    pub async fn wait_turn(&self, key: &str, id: u64) {
+        loop {
+            let finished = self.finished.notified();
+            let first = self.runs(key).first().map(|run| run.id);
+            if first.map_or(true, |first| first >= id) {
+                return;
+            }
+
+            finished.await;
+        }
+    }
+
+    /// wait until the job has no runs in progress
+    pub async fn wait_finished(&self, key: &str) {
+        loop {
+            let finished = self.finished.notified();
+            if !self.is_active(key) {
+                return;
+            }
+
+            finished.await;
+        }
+    }
+
+    /// return the job's active runs
+    pub fn runs(&self, key: &str) -> Vec<RunHandle> {
+        let inner = self.inner.lock().unwrap();
+        inner.runs.get(key).cloned().unwrap_or_default()
+    }
+
+    /// return the keys of the jobs with runs in progress
+    pub fn keys(&self) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        inner.runs.keys().cloned().collect()
+    }
+
+    /// return true if the job has a run in progress
+    pub fn is_active(&self, key: &str) -> bool {
+        !self.runs(key).is_empty()
+    }
+
+    fn register(&self, key: &str, force: bool) -> Option<RunGuard> {
+        let mut inner = self.inner.lock().unwrap();
+        let active = inner.runs.get(key).map_or(false, |runs| !runs.is_empty());
+        if active && !force {
+            return None;
+        }
+
+        inner.next_id += 1;
+        let handle = RunHandle {
+            id: inner.next_id,
+            pid: Arc::new(AtomicU64::new(0)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            step: Arc::new(AtomicU8::new(0)),
+            waiting: Arc::new(Mutex::new(None)),
+        };
+        inner
+            .runs
+            .entry(key.to_string())
+            .or_default()
+            .push(handle.clone());
+
+        Some(RunGuard {
+            key: key.to_string(),
+            handle,
+            runs: self.clone(),
+        })
+    }
+
+    fn remove(&self, key: &str, id: u64) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(runs) = inner.runs.get_mut(key) {
+                runs.retain(|run| run.id != id);
+                if runs.is_empty() {
+                    inner.runs.remove(key);
+                }
+            }
+        }
+
+        self.finished.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn start_and_finish() {
+        let active = ActiveRuns::new();
+
+        let first = active.start("job-1");
+        first.handle().set_pid(Some(42));
+        assert!(active.is_active("job-1"));
+        assert!(active.try_start("job-1").is_none());
+        assert!(active.try_start("job-2").is_some());
+
+        let second = active.start("job-1");
+        let runs = active.runs("job-1");
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].pid(), Some(42));
+        assert_eq!(runs[1].pid(), None);
+
+        runs[0].cancel();
+        assert!(first.handle().is_cancelled());
+        assert!(!second.handle().is_cancelled());
+        runs[1].set_paused(true);
+        assert!(second.handle().is_paused());
+        assert!(!first.handle().is_paused());
+        runs[0].set_step(3);
+        assert_eq!(first.handle().step(), 3);
+        assert_eq!(second.handle().step(), 0);
+
+        let waiting = tokio::spawn({
+            let active = active.clone();
+            async move { active.wait_start("job-1").await.handle().id }
+        });
+        tokio::task::yield_now().await;
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        drop(second);
+        assert!(waiting.await.unwrap() > 0);
+        active.wait_finished("job-1").await;
+        assert!(!active.is_active("job-1"));
+    }
+
+    #[tokio::test]
+    async fn wait_turn_and_cancel() {
+        let active = ActiveRuns::new();
+        let first = active.start("job-1");
+        let second = active.start("job-1");
+        let id = second.handle().id;
+
+        // the second run waits its turn until it is cancelled
+        let turn = {
+            let active = active.clone();
+            async move { active.wait_turn("job-1", id).await }
+        };
+        let waiting = tokio::spawn({
+            let handle = second.handle().clone();
+            async move { handle.unless_cancelled(turn).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        active.runs("job-1")[1].cancel();
+        assert_eq!(waiting.await.unwrap(), None);
+        drop(second);
+
+        // a run waits its turn behind the earlier runs only
+        let third = active.start("job-1");
+        let handle = third.handle().clone();
+        let turn = {
+            let active = active.clone();
+            let id = handle.id;
+            async move { active.wait_turn("job-1", id).await }
+        };
+        drop(first);
+        assert_eq!(handle.unless_cancelled(turn).await, Some(()));
+    }
+}
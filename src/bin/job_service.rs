@@ -1,13 +1,21 @@
-//!
+//! job_service - runs the job scheduler service
 //!
 
 use anyhow::Result;
 use log::{debug, error, info};
 // use clap::{Parser, Subcommand}
 use domain_keys::models::Model;
+use job_scheduler::calendar_store::CalendarStore;
 use job_scheduler::config::Config;
+use job_scheduler::executor::Executor;
+use job_scheduler::fire_log::FireLog;
 use job_scheduler::job_store::{Command, JobStore};
+use job_scheduler::limiter::Limiter;
 use job_scheduler::models::jobs::Job;
+use job_scheduler::output_spool::OutputSpool;
+use job_scheduler::run_store::RunStore;
+use job_scheduler::scheduler::Scheduler;
+use job_scheduler::workflow_runner::WorkflowRunner;
 use tokio::signal;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
@@ -24,10 +32,7 @@ async fn insert(request_channel: mpsc::Sender<Command>, model: Model<Job>) -> Re
             cbd,
             String::from("*").repeat(20)
         );
-        match cbd {
-            Ok(data) => data,
-            _ => None,
-        }
+        cbd?
     });
 
     // 3) create and send the request message
@@ -36,7 +41,7 @@ async fn insert(request_channel: mpsc::Sender<Command>, model: Model<Job>) -> Re
     debug!("Insert call result {:?}", r);
 
     // 4)
-    let data = join.await?;
+    let data = join.await??;
     info!("Insert callback data: {:?}", data);
 
     Ok(())
@@ -51,10 +56,7 @@ async fn find(request_channel: mpsc::Sender<Command>, key: &str) -> Option<Model
     let join = tokio::task::spawn(async move {
         let cbd = rx.await;
         info!("CALLBACK data: {:?} {}", cbd, String::from("*").repeat(25));
-        match cbd {
-            Ok(data) => data,
-            _ => None,
-        }
+        cbd.unwrap_or_default()
     });
 
     // 3) create and send the request message
@@ -83,6 +85,35 @@ async fn main() -> Result<()> {
         }
     });
 
+    // start the calendar store and the scheduler; fired jobs are run by the executor
+    let calendars = CalendarStore::with_folder(&config.calendar_folder()).await;
+    let (fire_tx, mut fire_rx) = mpsc::channel::<Model<Job>>(64);
+    let fire_log = FireLog::with_file(&config.fire_log_file());
+    Scheduler::start(&store, &calendars, fire_log, fire_tx.clone());
+    let (completed_tx, completed_rx) = mpsc::channel::<Model<Job>>(64);
+    WorkflowRunner::start(&store, completed_rx, fire_tx);
+
+    let limiter = Limiter::new(config.max_concurrent_jobs, &config.topic_limits);
+    let runs = RunStore::with_limit(config.run_history_limit).await;
+    let output = OutputSpool::with_folder(
+        &config.output_folder(),
+        config.output_tail_lines,
+        config.output_tail_bytes,
+    )
+    .with_retention(config.output_retention_runs);
+    let executor = Executor::with_limiter(&store, limiter)
+        .with_run_store(&runs)
+        .with_output_spool(output)
+        .with_completions(completed_tx);
+    executor.cancel_removed(&store);
+    tokio::spawn(async move {
+        while let Some(model) = fire_rx.recv().await {
+            info!("fired job: {} {}", model.key, model.value.action);
+            let executor = executor.clone();
+            tokio::spawn(async move { executor.fire(model).await });
+        }
+    });
+
     // let request_channel = store.request_channel();
 
     // create a new job and insert into job store
@@ -0,0 +1,198 @@
+/// CalendarStore.  Holds the named holiday/blackout calendars, loaded from and saved to json files
+/// in the calendar folder; implemented with messaging like the JobStore.  the name of each calendar
+/// that is inserted or removed is broadcast, so the scheduler can apply the change.
+///
+use crate::models::calendar::Calendar;
+use anyhow::Result;
+use hashbrown::HashMap;
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+
+#[derive(Debug)]
+pub enum Command {
+    Insert(Box<Calendar>, oneshot::Sender<Result<Calendar>>), // validates and saves to file
+    Find(String, oneshot::Sender<Option<Calendar>>),
+    Remove(String, oneshot::Sender<Option<Calendar>>),
+    List(oneshot::Sender<Vec<Calendar>>),
+}
+
+#[derive(Debug)]
+pub struct CalendarStore {
+    req_sender: mpsc::Sender<Command>,
+    broadcaster: broadcast::Sender<String>,
+}
+
+impl CalendarStore {
+    /// create the store and load all of the *.json calendars in the folder
+    pub async fn with_folder(folder: &Path) -> CalendarStore {
+        let calendars = match CalendarStore::load_calendars(folder) {
+            Ok(calendars) => calendars,
+            Err(e) => {
+                warn!("could not load calendars from {:?}: {}", folder, e);
+                HashMap::new()
+            }
+        };
+
+        CalendarStore::with_calendars(folder, calendars).await
+    }
+
+    /// create the store with calendars that are already loaded
+    pub async fn with_calendars(
+        folder: &Path,
+        calendars: HashMap<String, Calendar>,
+    ) -> CalendarStore {
+        let (req_sender, mut req_receiver) = mpsc::channel::<Command>(64);
+        let (broadcaster, _) = broadcast::channel::<String>(64);
+        let changed_tx = broadcaster.clone();
+        let folder: PathBuf = folder.to_path_buf();
+        let mut map = calendars;
+
+        tokio::spawn(async move {
+            while let Some(cmd) = req_receiver.recv().await {
+                info!("calendar req recv: {:?}", cmd);
+                match cmd {
+                    Command::Insert(calendar, tx) => {
+                        let calendar = *calendar;
+                        let result = calendar.write_file(&folder).map(|_| {
+                            map.insert(calendar.name.to_string(), calendar.clone());
+                            calendar
+                        });
+
+                        if let Ok(calendar) = &result {
+                            let _ = changed_tx.send(calendar.name.to_string());
+                        }
+                        let _ = tx.send(result);
+                    }
+                    Command::Find(name, tx) => {
+                        let _ = tx.send(map.get(&name).cloned());
+                    }
+                    Command::Remove(name, tx) => {
+                        let removed = map.remove(&name);
+                        if removed.is_some() {
+                            let path = folder.join(format!("{}.json", name));
+                            if let Err(e) = std::fs::remove_file(&path) {
+                                error!("could not remove calendar file {:?}: {}", path, e);
+                            }
+                            let _ = changed_tx.send(name.to_string());
+                        }
+
+                        let _ = tx.send(removed);
+                    }
+                    Command::List(tx) => {
+                        let mut list: Vec<Calendar> = map.values().cloned().collect();
+                        list.sort_by(|a, b| a.name.cmp(&b.name));
+
+                        let _ = tx.send(list);
+                    }
+                }
+            }
+
+            req_receiver.close();
+        });
+
+        CalendarStore {
+            req_sender,
+            broadcaster,
+        }
+    }
+
+    /// clients get access to the request channel to send commands
+    pub fn request_channel(&self) -> mpsc::Sender<Command> {
+        self.req_sender.clone()
+    }
+
+    /// subscribe to the names of the calendars that are inserted or removed
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.broadcaster.subscribe()
+    }
+
+    /// read all of the *.json calendar files in the folder; a missing folder has no calendars
+    pub fn load_calendars(folder: &Path) -> Result<HashMap<String, Calendar>> {
+        let mut map = HashMap::new();
+        if !folder.exists() {
+            return Ok(map);
+        }
+
+        for entry in std::fs::read_dir(folder)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+
+            match Calendar::read_file(&path) {
+                Ok(calendar) => {
+                    map.insert(calendar.name.to_string(), calendar);
+                }
+                Err(e) => error!("skipping calendar file {:?}: {}", path, e),
+            }
+        }
+
+        info!("loaded {} calendars from {:?}", map.len(), folder);
+
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[tokio::test]
+    async fn insert_find_remove() {
+        let folder = std::env::temp_dir().join(format!("calendars-{}", std::process::id()));
+        let store = CalendarStore::with_folder(&folder).await;
+        let channel = store.request_channel();
+        let mut changes = store.subscribe();
+
+        let mut calendar = Calendar::new("us-holidays");
+        calendar.exclude_date(NaiveDate::from_ymd_opt(2022, 12, 25).unwrap(), "christmas");
+
+        let (tx, rx) = oneshot::channel();
+        channel
+            .send(Command::Insert(Box::new(calendar.clone()), tx))
+            .await
+            .unwrap();
+        assert_eq!(rx.await.unwrap().unwrap(), calendar);
+
+        // a bad name is rejected
+        let (tx, rx) = oneshot::channel();
+        let bad = Calendar::new("../nope");
+        channel
+            .send(Command::Insert(Box::new(bad), tx))
+            .await
+            .unwrap();
+        assert!(rx.await.unwrap().is_err());
+        assert_eq!(changes.try_recv().unwrap(), "us-holidays");
+        assert!(changes.try_recv().is_err());
+
+        // the file was saved and can be loaded again
+        let loaded = CalendarStore::load_calendars(&folder).unwrap();
+        assert_eq!(loaded.get("us-holidays"), Some(&calendar));
+
+        let (tx, rx) = oneshot::channel();
+        channel
+            .send(Command::Find("us-holidays".to_string(), tx))
+            .await
+            .unwrap();
+        assert_eq!(rx.await.unwrap(), Some(calendar.clone()));
+
+        let (tx, rx) = oneshot::channel();
+        channel
+            .send(Command::Remove("us-holidays".to_string(), tx))
+            .await
+            .unwrap();
+        assert_eq!(rx.await.unwrap(), Some(calendar));
+        assert_eq!(changes.try_recv().unwrap(), "us-holidays");
+
+        let (tx, rx) = oneshot::channel();
+        channel.send(Command::List(tx)).await.unwrap();
+        assert!(rx.await.unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+}
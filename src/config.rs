@@ -1,4 +1,5 @@
 use anyhow::Result;
+use hashbrown::HashMap;
 use log::{info, warn};
 use serde::Deserialize;
 use std::io::prelude::*;
@@ -6,6 +7,7 @@ use std::{
     fs::File,
     io::{BufReader, Read},
     net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
@@ -17,6 +19,18 @@ pub struct Config {
     pub port: u16,
     pub logging_config: String,
     pub data_folder: String,
+    #[serde(default)]
+    pub max_concurrent_jobs: usize, // 0 = no limit
+    #[serde(default)]
+    pub topic_limits: HashMap<String, usize>, // max concurrent runs per job topic
+    #[serde(default = "Config::default_run_history_limit")]
+    pub run_history_limit: usize, // runs kept per job
+    #[serde(default = "Config::default_output_tail_lines")]
+    pub output_tail_lines: usize, // lines of each output stream kept on the job
+    #[serde(default = "Config::default_output_tail_bytes")]
+    pub output_tail_bytes: usize, // bytes of each output stream kept on the job
+    #[serde(default = "Config::default_output_retention_runs")]
+    pub output_retention_runs: usize, // runs of each job whose spooled output is kept
 }
 
 impl Config {
@@ -46,9 +60,46 @@ impl Config {
             port: self.port,
             logging_config: self.logging_config.to_string(),
             data_folder: self.data_folder.to_string(),
+            max_concurrent_jobs: self.max_concurrent_jobs,
+            topic_limits: self.topic_limits.clone(),
+            run_history_limit: self.run_history_limit,
+            output_tail_lines: self.output_tail_lines,
+            output_tail_bytes: self.output_tail_bytes,
+            output_retention_runs: self.output_retention_runs,
         }
     }
 
+    fn default_run_history_limit() -> usize {
+        crate::run_store::DEFAULT_RUN_HISTORY_LIMIT
+    }
+
+    fn default_output_tail_lines() -> usize {
+        crate::output_spool::DEFAULT_OUTPUT_TAIL_LINES
+    }
+
+    fn default_output_tail_bytes() -> usize {
+        crate::output_spool::DEFAULT_OUTPUT_TAIL_BYTES
+    }
+
+    fn default_output_retention_runs() -> usize {
+        crate::output_spool::DEFAULT_OUTPUT_RETENTION_RUNS
+    }
+
+    /// return the folder that holds the calendar json files
+    pub fn calendar_folder(&self) -> PathBuf {
+        Path::new(&self.data_folder).join("calendars")
+    }
+
+    /// return the file that records the last time each job fired
+    pub fn fire_log_file(&self) -> PathBuf {
+        Path::new(&self.data_folder).join("last-fired.json")
+    }
+
+    /// return the folder that holds the spooled output of each run
+    pub fn output_folder(&self) -> PathBuf {
+        Path::new(&self.data_folder).join("output")
+    }
+
     /// start the logger
     pub fn start_logger(&self) -> Result<()> {
         log4rs::init_file(&self.logging_config, Default::default())?;
@@ -84,6 +135,7 @@ impl Config {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -94,6 +146,44 @@ mod tests {
         assert!(!config.data_folder.is_empty());
     }
 
+    #[test]
+    fn calendar_folder() {
+        let config = Config::read_config("tests/server-config.toml").unwrap();
+        assert_eq!(config.calendar_folder(), PathBuf::from("data/calendars"));
+    }
+
+    #[test]
+    fn fire_log_file() {
+        let config = Config::read_config("tests/server-config.toml").unwrap();
+        assert_eq!(
+            config.fire_log_file(),
+            PathBuf::from("data/last-fired.json")
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let config = Config::read_config("tests/server-config.toml").unwrap();
+        assert_eq!(config.max_concurrent_jobs, 8);
+        assert_eq!(config.topic_limits.get("backup"), Some(&1));
+
+        let config = Config::read_config("config/server-config.toml").unwrap();
+        assert_eq!(config.max_concurrent_jobs, 0);
+        assert!(config.topic_limits.is_empty());
+        assert_eq!(config.run_history_limit, 100);
+        assert_eq!(config.output_tail_lines, 200);
+        assert_eq!(config.output_tail_bytes, 65536);
+        assert_eq!(config.output_retention_runs, 100);
+    }
+
+    #[test]
+    fn output() {
+        let config = Config::read_config("tests/server-config.toml").unwrap();
+        assert_eq!(config.output_folder(), PathBuf::from("data/output"));
+        assert_eq!(config.output_tail_lines, 50);
+        assert_eq!(config.output_retention_runs, 10);
+    }
+
     #[test]
     fn socket_address() {
         let config = Config::read_config("tests/server-config.toml").unwrap();
@@ -0,0 +1,1743 @@
+/// Executor.  Runs a job's action as an OS process in its own process group, records the pid,
+/// streams stdout lines into `Job.log` and stderr lines into `Job.errors`, and maps the exit code
+/// onto `Status::Processed`.  Every update is sent back through the JobStore, which sets only the
+/// run's fields on the job's current model, so the job can be edited while it runs.
+///
+/// runs are limited by the executor's Limiter; a run that has to wait is set to New(128), queued.
+///
+/// a job with `timeout_seconds` that runs too long gets SIGTERM, then SIGKILL after the grace
+/// period, both sent to the whole process group.
+///
+/// when a job fires while a run is still active its overlap policy decides: skip the new run,
+/// queue it behind the active one, run both, or cancel the active run and replace it.  a queued
+/// run waits in the executor and leaves the job's status alone; parallel runs each keep their own
+/// pid and output in their JobRun, and only the run that owns the job updates it.
+///
+/// with a RunStore, every run is recorded as a JobRun and the job's `run_id` links to it.  a
+/// Completed event is broadcast with the final model when the run, including retries, is over, and
+/// the model is sent to the completions channel, if there is one, which never drops it.
+///
+/// a run's output is spooled by the executor's OutputSpool; the model keeps only the tail of
+/// stdout in `Job.log` and stderr in `Job.errors`, marked when lines were dropped.
+///
+/// a run's results are parsed as json from the job's `results_file` if the run wrote it, else
+/// from the last stdout line marked `::results::`.
+///
+/// a running job reports progress with `::progress::<step> <message>` stdout lines, or the same
+/// lines appended to the file in `JOB_PROGRESS_FILE`; each report sets Active(step) and the job's
+/// `progress` message, and is broadcast as a Progress event.  the file is created per run in the
+/// job's output folder, private to the run-as user, so the variable is only set when output is
+/// spooled.
+///
+/// a job's resource limits are applied in the child before it execs the action: rlimits, then a
+/// cgroup of its own below the job's cgroup, then the limits' user and group, so nothing the job
+/// starts escapes them.  a run that can't be limited is not started.  only two broken limits are
+/// detected and set Processed(243): SIGXCPU from the cpu time rlimit, and an OOM kill in the run's
+/// own cgroup.  the address space, open files and processes rlimits make the job's own calls
+/// fail, which it reports like any other error with its exit code or signal.
+///
+/// an operator can cancel a job's active runs, Processed(242), or pause and resume them with
+/// SIGSTOP and SIGCONT; a paused job is Inactive(0).  removing a job from the store cancels it.
+use crate::active_runs::{ActiveRuns, RunGuard, RunHandle};
+use crate::job_store::{Command, JobStore};
+use crate::limiter::Limiter;
+use crate::models::jobs::{EventKind, Job, JobEvent, OverlapPolicy};
+use crate::models::progress::{Progress, PROGRESS_FILE_VAR};
+use crate::models::results::Results;
+use crate::models::runs::{JobRun, RunUpdate};
+use crate::output_spool::{OutputSpool, RunOutput};
+use crate::process::{read_lines, signal_group, spawn, stop_group, Output, RunCgroup};
+use crate::run_reports::{read_results, results_path, watch_progress};
+use crate::run_store::{self, RunStore};
+use anyhow::{anyhow, Result};
+use domain_keys::models::{Model, Status};
+use hashbrown::HashSet;
+use log::{error, info, warn};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// New value for a run waiting on a concurrency limit
+pub const QUEUED: u8 = 128;
+/// Processed value for a successful run
+pub const PROCESSED_OK: u8 = 0;
+/// Processed values 128 + exit code for a failed run; exit codes above 99 are reported as 99, the
+/// real code is kept in the run's JobRun
+pub const FAILED_EXIT_BASE: u8 = 128;
+/// Processed value when the process was killed by a signal
+pub const FAILED_SIGNALED: u8 = 240;
+/// Processed value when the run was killed for taking longer than its timeout
+pub const FAILED_TIMEOUT: u8 = 241;
+/// Processed value when the run was cancelled
+pub const FAILED_CANCELLED: u8 = 242;
+/// Processed value when the run was stopped by its cpu time rlimit or OOM killed in its cgroup
+pub const FAILED_LIMIT: u8 = 243;
+/// Inactive value while the run is paused
+pub const PAUSED: u8 = 0;
+/// Processed value when the process could not be started
+pub const FAILED_TO_START: u8 = 255;
+
+/// the default wait between SIGTERM and SIGKILL when a job times out
+pub const DEFAULT_KILL_GRACE_SECONDS: u64 = 10;
+
+/// how often collected output is flushed to the store while the job runs
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Run - a run in progress: its handle in ActiveRuns, its record in the RunStore and the tails of
+/// its output.  each run keeps its own pid and output, so parallel runs don't share them.
+struct Run {
+    handle: RunHandle,
+    record: JobRun,
+    output: RunOutput,
+}
+
+impl Run {
+    /// add an executor message to the job's log, counted in the log's tail like the job's output
+    fn log(&mut self, model: &mut Model<Job>, message: String) {
+        self.output.log.push(&mut model.value.log, message);
+    }
+
+    /// add an executor message to the job's errors, counted in the errors' tail
+    fn error(&mut self, model: &mut Model<Job>, message: String) {
+        self.output.errors.push(&mut model.value.errors, message);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Executor {
+    request_channel: mpsc::Sender<Command>,
+    limiter: Limiter,
+    active: ActiveRuns,
+    history: Option<mpsc::Sender<run_store::Command>>,
+    output: OutputSpool,
+    completions: Option<mpsc::Sender<Model<Job>>>,
+}
+
+impl Executor {
+    /// create an executor that sends job updates to the store, with no concurrency limits
+    pub fn new(store: &JobStore) -> Executor {
+        Executor::with_limiter(store, Limiter::default())
+    }
+
+    /// create an executor that limits concurrent runs
+    pub fn with_limiter(store: &JobStore, limiter: Limiter) -> Executor {
+        Executor {
+            request_channel: store.request_channel(),
+            limiter,
+            active: ActiveRuns::new(),
+            history: None,
+            output: OutputSpool::default(),
+            completions: None,
+        }
+    }
+
+    /// record every run in the run store
+    pub fn with_run_store(self, run_store: &RunStore) -> Executor {
+        Executor {
+            history: Some(run_store.request_channel()),
+            ..self
+        }
+    }
+
+    /// spool run output with the spool, keeping its tail on the model
+    pub fn with_output_spool(self, output: OutputSpool) -> Executor {
+        Executor { output, ..self }
+    }
+
+    /// send the final model of every run to the channel, e.g. to the WorkflowRunner
+    pub fn with_completions(self, completions: mpsc::Sender<Model<Job>>) -> Executor {
+        Executor {
+            completions: Some(completions),
+            ..self
+        }
+    }
+
+    /// the runs in progress
+    pub fn active_runs(&self) -> &ActiveRuns {
+        &self.active
+    }
+
+    /// cancel the job's active runs, stopping their process groups; the runs finish as
+    /// Processed(242).  returns an error if the job has no active run.
+    pub async fn cancel(&self, key: &str) -> Result<()> {
+        let model = self.find(key).await;
+        let runs = self.active.runs(key);
+        if runs.is_empty() {
+            return Err(anyhow!("job {} has no active run", key));
+        }
+
+        let grace = model
+            .as_ref()
+            .and_then(|model| model.value.kill_grace_seconds)
+            .unwrap_or(DEFAULT_KILL_GRACE_SECONDS);
+        let killers = self.cancel_runs(key, grace);
+
+        // the SIGKILL tasks are only needed until the runs finish
+        let active = self.active.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            active.wait_finished(&key).await;
+            for killer in killers {
+                killer.abort();
+            }
+        });
+
+        if let Some(model) = model {
+            info!("job {} cancelled", model.key);
+            self.notify(EventKind::Cancelled, "cancelled", &model).await;
+        }
+
+        Ok(())
+    }
+
+    /// pause the job's running processes with SIGSTOP; the runs set the job Inactive(0).  returns
+    /// an error if the job has no running process.
+    pub async fn pause(&self, key: &str) -> Result<()> {
+        let runs: Vec<RunHandle> = self
+            .active
+            .runs(key)
+            .into_iter()
+            .filter(|run| run.pid().is_some() && !run.is_paused())
+            .collect();
+        if runs.is_empty() {
+            return Err(anyhow!("job {} has no running process to pause", key));
+        }
+
+        for run in runs {
+            run.set_paused(true);
+            signal_group(run.pid(), libc::SIGSTOP);
+        }
+
+        self.notify_run(key, EventKind::Paused, "paused").await;
+
+        Ok(())
+    }
+
+    /// resume the job's paused processes with SIGCONT; the runs set the job Active again.
+    /// returns an error if the job is not paused.
+    pub async fn resume(&self, key: &str) -> Result<()> {
+        let runs: Vec<RunHandle> = self
+            .active
+            .runs(key)
+            .into_iter()
+            .filter(|run| run.is_paused())
+            .collect();
+        if runs.is_empty() {
+            return Err(anyhow!("job {} is not paused", key));
+        }
+
+        for run in runs {
+            run.set_paused(false);
+            signal_group(run.pid(), libc::SIGCONT);
+        }
+
+        self.notify_run(key, EventKind::Resumed, "resumed").await;
+
+        Ok(())
+    }
+
+    /// cancel the active runs of jobs removed from the store and delete their spooled output; if
+    /// the events lag, the active runs and spooled output are checked against the store so a
+    /// missed removal is still cleaned up
+    pub fn cancel_removed(&self, store: &JobStore) -> JoinHandle<()> {
+        let mut events = store.subscribe();
+        let executor = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(count)) => {
+                        // a missed Removed event would leave the run going
+                        warn!(
+                            "executor missed {} job events, checking the active runs",
+                            count
+                        );
+                        executor.discard_missing().await;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                if event.kind != EventKind::Removed {
+                    continue;
+                }
+
+                if let Some(model) = event.model {
+                    executor.discard(&model.key);
+                }
+            }
+        })
+    }
+
+    // cancel the removed job's runs, then delete their spooled output
+    fn discard(&self, key: &str) {
+        let executor = self.clone();
+        let key = key.to_string();
+
+        tokio::spawn(async move {
+            if executor.active.is_active(&key) {
+                if let Err(e) = executor.cancel(&key).await {
+                    warn!("could not cancel removed job: {}", e);
+                }
+                executor.active.wait_finished(&key).await;
+            }
+
+            executor.output.remove_job(&key);
+        });
+    }
+
+    // discard the runs and spooled output of jobs that are no longer in the store
+    async fn discard_missing(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .request_channel
+            .send(Command::List(0, usize::MAX, tx))
+            .await
+            .is_err()
+        {
+            error!("could not list jobs from the store");
+            return;
+        }
+
+        let keys: HashSet<String> = match rx.await {
+            Ok(list) => list.into_iter().map(|model| model.key).collect(),
+            Err(_) => return,
+        };
+        let mut missing: HashSet<String> = self.active.keys().into_iter().collect();
+        missing.extend(self.output.jobs());
+        for key in missing.difference(&keys) {
+            self.discard(key);
+        }
+    }
+
+    /// run a job the scheduler fired, applying the job's overlap policy if a run is still
+    /// active; returns the final model, or None if the run was skipped.
+    pub async fn fire(&self, model: Model<Job>) -> Option<Model<Job>> {
+        let mut waited = false;
+        let guard = match self.active.try_start(&model.key) {
+            Some(guard) => guard,
+            None => match model.value.overlap {
+                OverlapPolicy::Parallel => {
+                    self.overlap(&model, "running in parallel with the active run")
+                        .await;
+                    self.active.start(&model.key)
+                }
+                OverlapPolicy::Skip => {
+                    self.overlap(&model, "skipped, the previous run is still active")
+                        .await;
+                    return None;
+                }
+                OverlapPolicy::Queue => {
+                    // the job keeps the active run's status until the queued run starts; the
+                    // queued run is registered so it can be cancelled while it waits
+                    self.overlap(&model, "queued behind the active run").await;
+                    let guard = self.active.start(&model.key);
+                    let turn = {
+                        let active = self.active.clone();
+                        let key = model.key.to_string();
+                        let id = guard.handle().id;
+                        async move { active.wait_turn(&key, id).await }
+                    };
+                    if guard.handle().unless_cancelled(turn).await.is_none() {
+                        info!("job {} queued run cancelled", model.key);
+                        return None;
+                    }
+
+                    waited = true;
+                    guard
+                }
+                OverlapPolicy::Replace => {
+                    self.overlap(&model, "cancelling the active run to replace it")
+                        .await;
+                    let grace = model
+                        .value
+                        .kill_grace_seconds
+                        .unwrap_or(DEFAULT_KILL_GRACE_SECONDS);
+                    let killers = self.cancel_runs(&model.key, grace);
+                    let guard = self.active.wait_start(&model.key).await;
+                    for killer in killers {
+                        killer.abort();
+                    }
+
+                    waited = true;
+                    guard
+                }
+            },
+        };
+
+        // a run that waited starts the job as it is now, unless it was removed meanwhile
+        let model = if waited {
+            match self.find(&model.key).await {
+                Some(current) => current,
+                None => {
+                    info!("job {} was removed while its run waited", model.key);
+                    return None;
+                }
+            }
+        } else {
+            model
+        };
+
+        Some(self.run_with(model, &guard).await)
+    }
+
+    /// run the job's action to completion, retrying failures according to the job's retry
+    /// policy, and return the final model; a job that runs out of attempts is Blocked.
+    pub async fn run(&self, model: Model<Job>) -> Model<Job> {
+        let guard = self.active.start(&model.key);
+        self.run_with(model, &guard).await
+    }
+
+    async fn run_with(&self, model: Model<Job>, guard: &RunGuard) -> Model<Job> {
+        let record = JobRun::new(&model.key);
+        let mut model = model;
+        model.value.run_id = Some(record.id.to_string());
+        self.record(&record).await;
+
+        let mut run = Run {
+            handle: guard.handle().clone(),
+            output: self.output.run_output(&model.key, &record.id),
+            record,
+        };
+        let model = self.run_attempts(model, &mut run).await;
+
+        run.record.finish(&model);
+        self.record(&run.record).await;
+        self.notify(EventKind::Completed, "run completed", &model)
+            .await;
+        if let Some(completions) = &self.completions {
+            if completions.send(model.clone()).await.is_err() {
+                warn!("could not send the completed run of job {}", model.key);
+            }
+        }
+
+        model
+    }
+
+    async fn run_attempts(&self, model: Model<Job>, run: &mut Run) -> Model<Job> {
+        let mut model = model;
+        model.value.log.clear();
+        model.value.errors.clear();
+        model.value.results = None;
+        model.value.progress = None;
+
+        let topic = model.value.topic.to_string();
+        let _permit = match self.limiter.try_acquire(&topic) {
+            Some(permit) => permit,
+            None => {
+                info!("job {} queued, topic {} is at its limit", model.key, topic);
+                model = Job::update_model(&model, Status::New(QUEUED));
+                self.update(&model, run).await;
+
+                let permit = {
+                    let limiter = self.limiter.clone();
+                    async move { limiter.acquire(&topic).await }
+                };
+                match run.handle.unless_cancelled(permit).await {
+                    Some(permit) => permit,
+                    None => {
+                        info!("job {} cancelled while queued", model.key);
+                        run.error(&mut model, "cancelled".to_string());
+                        let model = Job::update_model(&model, Status::Processed(FAILED_CANCELLED));
+                        self.update(&model, run).await;
+
+                        return model;
+                    }
+                }
+            }
+        };
+
+        let policy = match model.value.retry.clone() {
+            Some(policy) => policy,
+            None => return self.run_once(model, run).await,
+        };
+
+        let mut attempt = 1;
+        loop {
+            run.log(
+                &mut model,
+                format!("attempt {} of {}", attempt, policy.max_attempts),
+            );
+            model = self.run_once(model, run).await;
+
+            let code = match model.status {
+                Status::Processed(FAILED_CANCELLED) => return model,
+                Status::Processed(code) if code >= FAILED_EXIT_BASE => code,
+                _ => return model,
+            };
+
+            if !policy.should_retry(attempt, run.record.exit_code) {
+                let message = format!("attempt {} failed with code {}, giving up", attempt, code);
+                warn!("job {} {}", model.key, message);
+                run.log(&mut model, message);
+
+                let model = Job::update_model(&model, Status::Blocked(code));
+                self.update(&model, run).await;
+
+                return model;
+            }
+
+            let delay = policy.delay(attempt);
+            let message = format!(
+                "attempt {} failed with code {}, retry in {} ms",
+                attempt,
+                code,
+                delay.as_millis()
+            );
+            info!("job {} {}", model.key, message);
+            run.log(&mut model, message);
+            self.update(&model, run).await;
+
+            // a run cancelled during the delay ends in run_once without starting again
+            run.handle.unless_cancelled(tokio::time::sleep(delay)).await;
+            attempt += 1;
+        }
+    }
+
+    /// run the action a single time, adding to the job's log and errors
+    async fn run_once(&self, model: Model<Job>, run: &mut Run) -> Model<Job> {
+        let mut model = model;
+        model.value.pid = None;
+        run.record.exit_code = None;
+
+        if run.handle.is_cancelled() {
+            run.error(&mut model, "cancelled".to_string());
+            let model = Job::update_model(&model, Status::Processed(FAILED_CANCELLED));
+            self.update(&model, run).await;
+
+            return model;
+        }
+
+        let limits = model.value.limits.clone().unwrap_or_default();
+        let results_file = results_path(&model.value);
+        if let Some(path) = &results_file {
+            // a file left by an earlier run is not this run's results
+            if let Err(e) = std::fs::remove_file(path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("could not remove old results {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        // without a progress file the job can still report progress on stdout
+        let progress_file = match self.output.create_progress_file(
+            &model.key,
+            &run.record.id,
+            limits.user,
+            limits.group,
+        ) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                warn!(
+                    "job {} runs without {}: {}",
+                    model.key, PROGRESS_FILE_VAR, e
+                );
+                None
+            }
+        };
+        let env: Vec<(String, String)> = progress_file
+            .iter()
+            .map(|path| (PROGRESS_FILE_VAR.to_string(), path.display().to_string()))
+            .collect();
+
+        let cgroup = match RunCgroup::create(&limits, &run.record.id) {
+            Ok(cgroup) => cgroup,
+            Err(e) => {
+                error!("job {} could not be limited: {}", model.key, e);
+                run.error(&mut model, format!("could not apply limits: {}", e));
+                let model = Job::update_model(&model, Status::Processed(FAILED_TO_START));
+                self.update(&model, run).await;
+
+                return model;
+            }
+        };
+
+        let mut process = match spawn(&model.value.action, &limits, cgroup.as_ref(), &env) {
+            Ok(process) => process,
+            Err(e) => {
+                if let Some(cgroup) = cgroup {
+                    cgroup.remove();
+                }
+                error!("job {} could not start: {}", model.key, e);
+                run.error(&mut model, format!("could not start: {}", e));
+                let model = Job::update_model(&model, Status::Processed(FAILED_TO_START));
+                self.update(&model, run).await;
+
+                return model;
+            }
+        };
+
+        model.value.pid = Some(u64::from(process.id()));
+        run.handle.set_pid(model.value.pid);
+        info!("job {} started, pid: {:?}", model.key, model.value.pid);
+        if !limits.is_empty() {
+            run.log(&mut model, limits.to_string());
+        }
+        if run.handle.is_cancelled() {
+            // cancelled while starting, before the pid was known
+            signal_group(model.value.pid, libc::SIGKILL);
+        } else if run.handle.is_paused() {
+            // paused between attempts
+            signal_group(model.value.pid, libc::SIGSTOP);
+        }
+        model = Job::update_model(&model, running_status(&run.handle));
+        self.update(&model, run).await;
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut readers = 0;
+        if let Some(stdout) = process.stdout.take() {
+            read_lines(stdout, run.output.log.open(), tx.clone(), Output::Stdout);
+            readers += 1;
+        }
+        if let Some(stderr) = process.stderr.take() {
+            read_lines(stderr, run.output.errors.open(), tx.clone(), Output::Stderr);
+            readers += 1;
+        }
+        let watching = Arc::new(AtomicBool::new(true));
+        if let Some(path) = &progress_file {
+            watch_progress(path.clone(), tx, watching.clone());
+        }
+
+        let mut waiter = tokio::task::spawn_blocking(move || process.wait());
+        let deadline = model
+            .value
+            .timeout_seconds
+            .map(|seconds| tokio::time::Instant::now() + Duration::from_secs(seconds));
+        let mut killer: Option<JoinHandle<()>> = None;
+
+        // read until the process closes both pipes, waking at least every flush interval to check
+        // the deadline and to pick up a pause or resume
+        let mut marked_results: Option<String> = None;
+        let mut closed = 0;
+        let mut pending = false;
+        let mut flushed = Instant::now();
+        while closed < readers {
+            let next = match tokio::time::timeout_at(wake_at(deadline, &killer), rx.recv()).await {
+                Ok(Some(next)) => Some(next),
+                Ok(None) => break,
+                Err(_) => None,
+            };
+            if is_overdue(deadline, &killer) {
+                killer = Some(self.terminate(&mut model, run).await);
+            }
+
+            match next {
+                Some(Output::Stdout(line)) => {
+                    if let Some(report) = Progress::marked(&line) {
+                        self.progress(&mut model, run, report).await;
+                        continue;
+                    }
+                    if let Some(json) = Results::marked(&line) {
+                        marked_results = Some(json.to_string());
+                    }
+                    run.output.log.push(&mut model.value.log, line);
+                    pending = true;
+                }
+                Some(Output::Stderr(line)) => {
+                    run.output.errors.push(&mut model.value.errors, line);
+                    pending = true;
+                }
+                Some(Output::Progress(report)) => self.progress(&mut model, run, &report).await,
+                Some(Output::Closed) => closed += 1,
+                None => (),
+            }
+
+            let status = running_status(&run.handle);
+            if status != model.status || (pending && flushed.elapsed() >= FLUSH_INTERVAL) {
+                model = Job::update_model(&model, status);
+                self.update(&model, run).await;
+                pending = false;
+                flushed = Instant::now();
+            }
+        }
+
+        // the process may close its output and keep running, so the deadline applies here too
+        let result = loop {
+            match tokio::time::timeout_at(wake_at(deadline, &killer), &mut waiter).await {
+                Ok(result) => break result,
+                Err(_) => {
+                    if is_overdue(deadline, &killer) {
+                        killer = Some(self.terminate(&mut model, run).await);
+                    }
+
+                    let status = running_status(&run.handle);
+                    if status != model.status {
+                        model = Job::update_model(&model, status);
+                        self.update(&model, run).await;
+                    }
+                }
+            }
+        };
+
+        run.handle.set_pid(None);
+        watching.store(false, Ordering::SeqCst);
+        if let Some(path) = &progress_file {
+            let _ = std::fs::remove_file(path);
+        }
+
+        let code = match result {
+            Ok(Ok(_)) if killer.is_some() => FAILED_TIMEOUT,
+            Ok(Ok(_)) if run.handle.is_cancelled() => {
+                run.error(&mut model, "cancelled".to_string());
+                FAILED_CANCELLED
+            }
+            Ok(Ok(status)) if status.signal() == Some(libc::SIGXCPU) => {
+                run.error(&mut model, "cpu time limit exceeded".to_string());
+                FAILED_LIMIT
+            }
+            Ok(Ok(_)) if cgroup.as_ref().map_or(0, RunCgroup::oom_kills) > 0 => {
+                run.error(&mut model, "memory limit exceeded".to_string());
+                FAILED_LIMIT
+            }
+            Ok(Ok(status)) => {
+                run.record.exit_code = exit_code(&status);
+                processed_code(&status)
+            }
+            Ok(Err(e)) => {
+                run.error(&mut model, format!("wait failed: {}", e));
+                FAILED_SIGNALED
+            }
+            Err(e) => {
+                run.error(&mut model, format!("wait failed: {}", e));
+                FAILED_SIGNALED
+            }
+        };
+
+        if let Some(killer) = killer {
+            killer.abort();
+        }
+        if let Some(cgroup) = cgroup {
+            cgroup.remove();
+        }
+
+        match read_results(results_file.as_deref(), marked_results) {
+            Ok(results) => model.value.results = results,
+            Err(e) => {
+                warn!("job {} {}", model.key, e);
+                run.error(&mut model, e.to_string());
+            }
+        }
+
+        info!("job {} finished, processed code: {}", model.key, code);
+        let model = Job::update_model(&model, Status::Processed(code));
+        self.update(&model, run).await;
+
+        model
+    }
+
+    /// apply a progress report: set Active(step) and the message, update the store and broadcast
+    /// a Progress event; a bad report is logged and ignored
+    async fn progress(&self, model: &mut Model<Job>, run: &mut Run, report: &str) {
+        let progress = match Progress::parse(report) {
+            Ok(progress) => progress,
+            Err(e) => {
+                warn!("job {} {}", model.key, e);
+                return;
+            }
+        };
+
+        run.handle.set_step(progress.step);
+        if progress.message.is_some() {
+            model.value.progress = progress.message;
+        }
+        *model = Job::update_model(model, running_status(&run.handle));
+        self.update(model, run).await;
+
+        let message = match &model.value.progress {
+            Some(message) => format!("progress: {} {}", progress.step, message),
+            None => format!("progress: {}", progress.step),
+        };
+        self.notify(EventKind::Progress, &message, model).await;
+    }
+
+    /// send SIGTERM to the job's process group and return a task that sends SIGKILL after the
+    /// grace period; the caller aborts the task if the process exits first.
+    async fn terminate(&self, model: &mut Model<Job>, run: &mut Run) -> JoinHandle<()> {
+        let timeout = model.value.timeout_seconds.unwrap_or_default();
+        let grace = model
+            .value
+            .kill_grace_seconds
+            .unwrap_or(DEFAULT_KILL_GRACE_SECONDS);
+        let message = format!("timed out after {} seconds", timeout);
+
+        warn!("job {} {}, sending SIGTERM", model.key, message);
+        run.error(model, message.to_string());
+
+        self.notify(EventKind::TimedOut, &message, model).await;
+
+        stop_group(model.value.pid, grace)
+    }
+
+    /// cancel the job's active runs, stopping their processes; returns the SIGKILL tasks
+    fn cancel_runs(&self, key: &str, grace: u64) -> Vec<JoinHandle<()>> {
+        self.active
+            .runs(key)
+            .iter()
+            .map(|run| {
+                run.cancel();
+                let killer = stop_group(run.pid(), grace);
+                if run.is_paused() {
+                    // a stopped process only sees the SIGTERM once it continues
+                    run.set_paused(false);
+                    signal_group(run.pid(), libc::SIGCONT);
+                }
+
+                killer
+            })
+            .collect()
+    }
+
+    /// broadcast an event about the job's active runs with its current model
+    async fn notify_run(&self, key: &str, kind: EventKind, message: &str) {
+        info!("job {} {}", key, message);
+        if let Some(model) = self.find(key).await {
+            self.notify(kind, message, &model).await;
+        }
+    }
+
+    /// log and broadcast an overlap decision
+    async fn overlap(&self, model: &Model<Job>, decision: &str) {
+        let message = format!("overlap: {}", decision);
+        info!("job {} {}", model.key, message);
+
+        self.notify(EventKind::Overlap, &message, model).await;
+    }
+
+    /// broadcast an event about the job through the store
+    async fn notify(&self, kind: EventKind, message: &str, model: &Model<Job>) {
+        let event = JobEvent::new(kind, message, Some(model.clone()));
+        if self
+            .request_channel
+            .send(Command::Notify(Box::new(event)))
+            .await
+            .is_err()
+        {
+            warn!("could not send the {:?} event for job {}", kind, model.key);
+        }
+    }
+
+    /// send the run record to the run store, if there is one
+    async fn record(&self, run: &JobRun) {
+        if let Some(history) = &self.history {
+            let (tx, rx) = oneshot::channel();
+            let cmd = run_store::Command::Insert(Box::new(run.clone()), tx);
+            if history.send(cmd).await.is_err() || rx.await.is_err() {
+                warn!("could not record run {} of job {}", run.id, run.job_key);
+            }
+        }
+    }
+
+    /// read the model from the store
+    async fn find(&self, key: &str) -> Option<Model<Job>> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Find(key.to_string(), tx);
+        if self.request_channel.send(cmd).await.is_err() {
+            warn!("could not find job {} in the store", key);
+            return None;
+        }
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// record the run's fields in its JobRun and send them to the store.  the store only applies
+    /// them while the run owns the job, and a job removed from the store stays removed.
+    async fn update(&self, model: &Model<Job>, run: &mut Run) {
+        run.record.update(model);
+        self.record(&run.record).await;
+
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Update(Box::new(RunUpdate::new(model)), tx);
+        if self.request_channel.send(cmd).await.is_err() || rx.await.is_err() {
+            warn!("could not update job {} in the store", model.key);
+        }
+    }
+}
+
+/// when the run loop next wakes: after the flush interval, or at the deadline if that is sooner
+fn wake_at(
+    deadline: Option<tokio::time::Instant>,
+    killer: &Option<JoinHandle<()>>,
+) -> tokio::time::Instant {
+    let wake = tokio::time::Instant::now() + FLUSH_INTERVAL;
+    match deadline {
+        Some(deadline) if killer.is_none() => wake.min(deadline),
+        _ => wake,
+    }
+}
+
+/// true if the run is past its deadline and not yet being stopped
+fn is_overdue(deadline: Option<tokio::time::Instant>, killer: &Option<JoinHandle<()>>) -> bool {
+    killer.is_none() && deadline.map_or(false, |deadline| tokio::time::Instant::now() >= deadline)
+}
+
+/// the status of a run whose process is running: Active at its step, or Inactive while paused
+fn running_status(run: &RunHandle) -> Status {
+    if run.is_paused() {
+        Status::Inactive(PAUSED)
+    } else {
+        Status::Active(run.step())
+    }
+}
+
+/// map the process exit status onto the Status::Processed value range: 0..127 success,
+/// 128..255 failure
+pub fn processed_code(status: &ExitStatus) -> u8 {
+    match status.code() {
+        Some(0) => PROCESSED_OK,
+        Some(code) => FAILED_EXIT_BASE + code.clamp(1, 99) as u8,
+        None => FAILED_SIGNALED,
+    }
+}
+
+/// return the process's exit code, None if it didn't exit on its own
+pub fn exit_code(status: &ExitStatus) -> Option<u8> {
+    status.code().and_then(|code| u8::try_from(code).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::action::{Action, Exec};
+    use crate::models::limits::ResourceLimits;
+    use crate::models::retry::RetryPolicy;
+    use crate::output_spool::Stream;
+    use hashbrown::HashMap;
+    use tokio::sync::broadcast;
+
+    /// add the job to the store, as the service does before it runs
+    async fn insert(store: &JobStore, job: &Job) -> Model<Job> {
+        insert_model(store, Job::create_model(job)).await
+    }
+
+    async fn insert_model(store: &JobStore, model: Model<Job>) -> Model<Job> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Insert(Box::new(model), tx);
+        store.request_channel().send(cmd).await.unwrap();
+        rx.await.unwrap().unwrap()
+    }
+
+    async fn find(store: &JobStore, key: &str) -> Option<Model<Job>> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Find(key.to_string(), tx);
+        store.request_channel().send(cmd).await.unwrap();
+        rx.await.unwrap()
+    }
+
+    async fn run(action: &str) -> (JobStore, Model<Job>) {
+        let store = JobStore::new().await;
+        let executor = Executor::new(&store);
+        let model = insert(&store, &Job::new("test", action)).await;
+        let model = executor.run(model).await;
+
+        (store, model)
+    }
+
+    #[tokio::test]
+    async fn success() {
+        let (store, model) = run("echo hello; echo world").await;
+
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert!(model.value.pid.is_some());
+        assert_eq!(model.value.log, vec!["hello", "world"]);
+        assert!(model.value.errors.is_empty());
+
+        // the final model is in the store
+        assert_eq!(find(&store, &model.key).await, Some(model));
+    }
+
+    /// a file that holds the action until the test creates it, and the shell loop that waits for it
+    fn gate(name: &str) -> (std::path::PathBuf, String) {
+        let path = std::env::temp_dir().join(format!("gate-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let wait = format!("until [ -e {} ]; do sleep 0.01; done", path.display());
+
+        (path, wait)
+    }
+
+    #[tokio::test]
+    async fn edited_while_running() {
+        let store = JobStore::new().await;
+        let executor = Executor::new(&store);
+        let (gate, wait) = gate("edited");
+        let action = format!("{}; echo done", wait);
+        let model = insert(&store, &Job::new("edited", &action)).await;
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            let model = model.clone();
+            async move { executor.run(model).await }
+        });
+        wait_runs(&executor, &model.key, 1).await;
+
+        // the edit lands while the run is active and is kept by the run's updates
+        let mut edited = find(&store, &model.key).await.unwrap();
+        edited.value.timeout_seconds = Some(60);
+        edited.value.action = Action::from("echo edited");
+        insert_model(&store, edited).await;
+        std::fs::write(&gate, "").unwrap();
+
+        let finished = running.await.unwrap();
+        assert_eq!(finished.value.log, vec!["done"]);
+        let current = find(&store, &model.key).await.unwrap();
+        assert_eq!(current.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(current.value.log, vec!["done"]);
+        assert_eq!(current.value.timeout_seconds, Some(60));
+        assert_eq!(current.value.action, Action::from("echo edited"));
+
+        // a removed job isn't put back by its run
+        let _ = std::fs::remove_file(&gate);
+        let model = insert(&store, &Job::new("removed", &wait)).await;
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            let model = model.clone();
+            async move { executor.run(model).await }
+        });
+        wait_runs(&executor, &model.key, 1).await;
+        let cmd = Command::Remove(model.key.to_string());
+        store.request_channel().send(cmd).await.unwrap();
+        std::fs::write(&gate, "").unwrap();
+        running.await.unwrap();
+        assert_eq!(find(&store, &model.key).await, None);
+        let _ = std::fs::remove_file(&gate);
+    }
+
+    #[tokio::test]
+    async fn history() {
+        let store = JobStore::new().await;
+        let runs = RunStore::new().await;
+        let executor = Executor::new(&store).with_run_store(&runs);
+
+        let model = Job::create_model(&Job::new("history", "echo $$"));
+        let first = executor.run(model.clone()).await;
+        let second = executor.run(first.clone()).await;
+        assert_ne!(first.value.run_id, second.value.run_id);
+        assert_ne!(first.value.log, second.value.log);
+
+        let (tx, rx) = oneshot::channel();
+        runs.request_channel()
+            .send(run_store::Command::List(model.key.to_string(), 0, 10, tx))
+            .await
+            .unwrap();
+        let list = rx.await.unwrap();
+
+        assert_eq!(list.len(), 2);
+        assert_eq!(Some(&list[0].id), second.value.run_id.as_ref());
+        assert_eq!(list[0].log, second.value.log);
+        assert_eq!(list[1].log, first.value.log);
+        assert_eq!(list[1].status, Status::Processed(PROCESSED_OK));
+        assert!(list[1].finished.is_some());
+    }
+
+    #[tokio::test]
+    async fn spooled_output() {
+        let store = JobStore::new().await;
+        let folder = std::env::temp_dir().join(format!("spooled-{}", std::process::id()));
+        let output = OutputSpool::with_folder(&folder, 3, 1024);
+        let executor = Executor::new(&store).with_output_spool(output.clone());
+
+        let model = Job::create_model(&Job::new("chatty", "seq 1 50; echo oops >&2"));
+        let model = executor.run(model).await;
+        let run_id = model.value.run_id.clone().unwrap();
+
+        let path = output.path(&model.key, &run_id, Stream::Log).unwrap();
+        assert_eq!(
+            model.value.log,
+            vec![
+                format!(
+                    "[truncated: 47 earlier lines, full output in {}]",
+                    path.display()
+                ),
+                "48".to_string(),
+                "49".to_string(),
+                "50".to_string(),
+            ]
+        );
+        assert_eq!(model.value.errors, vec!["oops"]);
+
+        let full = output
+            .read(&model.key, &run_id, Stream::Log, 0, 100)
+            .unwrap();
+        assert_eq!(full.len(), 50);
+        assert_eq!(full[0], "1");
+        assert_eq!(
+            output
+                .read(&model.key, &run_id, Stream::Errors, 0, 100)
+                .unwrap(),
+            vec!["oops"]
+        );
+
+        // removing the job deletes its output
+        executor.cancel_removed(&store);
+        insert_model(&store, model.clone()).await;
+        store
+            .request_channel()
+            .send(Command::Remove(model.key.to_string()))
+            .await
+            .unwrap();
+        let job_folder = output.job_folder(&model.key).unwrap();
+        for _ in 0..100 {
+            if !job_folder.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!job_folder.exists());
+
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+
+    #[tokio::test]
+    async fn results() {
+        let store = JobStore::new().await;
+        let executor = Executor::new(&store);
+
+        let action =
+            r#"echo '::results::{"files": 1}'; echo '::results::{"bytes_backed_up": 1024}'"#;
+        let model = executor
+            .run(Job::create_model(&Job::new("backup", action)))
+            .await;
+        let results = model.value.results.unwrap();
+        assert_eq!(results["bytes_backed_up"], 1024);
+        assert!(results.get("files").is_none());
+
+        // the results file takes precedence, a stale one is removed first
+        let file = std::env::temp_dir().join(format!("results-{}.json", std::process::id()));
+        std::fs::write(&file, r#"{"stale": true}"#).unwrap();
+        let mut job = Job::new(
+            "backup",
+            &format!(
+                r#"echo '::results::{{}}'; echo '{{"rows": 7}}' > {}"#,
+                file.display()
+            ),
+        );
+        job.results_file = Some(file.display().to_string());
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.value.results.unwrap()["rows"], 7);
+
+        job.action = Action::from("true");
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.value.results, None);
+
+        // bad json is reported without failing the run
+        let model = executor
+            .run(Job::create_model(&Job::new(
+                "backup",
+                "echo ::results::{bad",
+            )))
+            .await;
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(model.value.results, None);
+        assert!(model.value.errors[0].starts_with("could not parse results:"));
+    }
+
+    #[tokio::test]
+    async fn progress() {
+        let store = JobStore::new().await;
+        let mut events = store.subscribe();
+        let folder = std::env::temp_dir().join(format!("progress-{}", std::process::id()));
+        let output = OutputSpool::with_folder(&folder, 100, 4096);
+        let executor = Executor::new(&store).with_output_spool(output);
+
+        // the progress file is the run's own, only its owner can read or write it
+        let action = r#"echo '::progress::10 starting'; sleep 0.5;
+            stat -c '%a' "$JOB_PROGRESS_FILE" >&2;
+            echo '50 half way' >> "$JOB_PROGRESS_FILE"; sleep 0.5;
+            echo '::progress::bad'; echo '::progress::90'; echo done"#;
+        let model = executor
+            .run(Job::create_model(&Job::new("backup", action)))
+            .await;
+        let _ = std::fs::remove_dir_all(&folder);
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(model.value.log, vec!["done"]);
+        assert_eq!(model.value.errors, vec!["600"]);
+        assert_eq!(model.value.progress, Some("half way".to_string()));
+
+        let mut reports = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if event.kind == EventKind::Progress {
+                reports.push((event.message, event.model.unwrap().status));
+            }
+        }
+        assert_eq!(
+            reports,
+            vec![
+                ("progress: 10 starting".to_string(), Status::Active(10)),
+                ("progress: 50 half way".to_string(), Status::Active(50)),
+                ("progress: 90 half way".to_string(), Status::Active(90)),
+            ]
+        );
+
+        // without spooled output there is no progress file, stdout reports still work
+        let executor = Executor::new(&store);
+        let action = r#"test -z "$JOB_PROGRESS_FILE" && echo '::progress::20 no file'"#;
+        let model = executor
+            .run(Job::create_model(&Job::new("backup", action)))
+            .await;
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(model.value.progress, Some("no file".to_string()));
+    }
+
+    #[tokio::test]
+    async fn non_utf8_output() {
+        let (_store, model) = run(r"printf 'caf\351\n'; seq 1 100000 | tail -n 1").await;
+
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(model.value.log, vec!["caf\u{fffd}", "100000"]);
+
+        let (_store, model) = run(r"printf 'caf\351\n'; seq 1 100000").await;
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(model.value.log.last().map(|s| s.as_str()), Some("100000"));
+    }
+
+    #[tokio::test]
+    async fn failure() {
+        let (_store, model) = run("echo started; echo broken >&2; exit 3").await;
+
+        assert_eq!(model.status, Status::Processed(131));
+        assert_eq!(model.value.log, vec!["started"]);
+        assert_eq!(model.value.errors, vec!["broken"]);
+
+        let (_store, model) = run("  ").await;
+        assert_eq!(model.status, Status::Processed(FAILED_TO_START));
+        assert_eq!(model.value.pid, None);
+    }
+
+    #[tokio::test]
+    async fn exec_action() {
+        let store = JobStore::new().await;
+        let executor = Executor::new(&store);
+
+        let mut exec = Exec {
+            program: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "printf '%s\\n' \"$@\" \"$GREETING\" \"$PWD\"".to_string(),
+                "sh".to_string(),
+                "two words".to_string(),
+                "it's; $HOME".to_string(),
+            ],
+            cwd: Some("/tmp".to_string()),
+            ..Default::default()
+        };
+        exec.env.insert("GREETING".to_string(), "hello".to_string());
+
+        let job = Job::with_action("exec", Action::Exec(exec));
+        let model = executor.run(Job::create_model(&job)).await;
+
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(
+            model.value.log,
+            vec!["two words", "it's; $HOME", "hello", "/tmp"]
+        );
+
+        let job = Job::with_action("missing", Action::exec("/no/such/program", &[]));
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.status, Status::Processed(FAILED_TO_START));
+    }
+
+    #[tokio::test]
+    async fn retry() {
+        let store = JobStore::new().await;
+        let executor = Executor::new(&store);
+
+        let mut policy = RetryPolicy::new(3);
+        policy.initial_delay_seconds = 0;
+
+        // fails every time
+        let mut job = Job::new("flaky", "echo trying; exit 3");
+        job.retry = Some(policy.clone());
+        let model = executor.run(Job::create_model(&job)).await;
+
+        assert_eq!(model.status, Status::Blocked(131));
+        assert_eq!(
+            model.value.log,
+            vec![
+                "attempt 1 of 3",
+                "trying",
+                "attempt 1 failed with code 131, retry in 0 ms",
+                "attempt 2 of 3",
+                "trying",
+                "attempt 2 failed with code 131, retry in 0 ms",
+                "attempt 3 of 3",
+                "trying",
+                "attempt 3 failed with code 131, giving up",
+            ]
+        );
+
+        // fails the first time only
+        let marker = std::env::temp_dir().join(format!("retry-{}", std::process::id()));
+        let action = format!(
+            "test -f {0} && exit 0; touch {0}; exit 75",
+            marker.display()
+        );
+        policy.retry_exit_codes = vec![75];
+        let mut job = Job::new("flaky", &action);
+        job.retry = Some(policy.clone());
+        let model = executor.run(Job::create_model(&job)).await;
+        let _ = std::fs::remove_file(&marker);
+
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(model.value.log.len(), 3);
+
+        // exit codes not in the list are not retried
+        let mut job = Job::new("flaky", "exit 1");
+        job.retry = Some(policy.clone());
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.status, Status::Blocked(129));
+        assert_eq!(model.value.log.len(), 2);
+
+        // codes above 99 share a status but are told apart by the real exit code
+        policy.retry_exit_codes = vec![127];
+        let mut job = Job::new("flaky", "exit 126");
+        job.retry = Some(policy.clone());
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.status, Status::Blocked(227));
+        assert_eq!(model.value.log.len(), 2);
+
+        job.action = Action::from("exit 127");
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.status, Status::Blocked(227));
+        assert_eq!(model.value.log.len(), 6);
+
+        // the executor's messages count toward the log's tail
+        let executor = Executor::new(&store).with_output_spool(OutputSpool::new(3, 1024));
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(
+            model.value.log,
+            vec![
+                "[truncated: 3 earlier lines]",
+                "attempt 2 failed with code 227, retry in 0 ms",
+                "attempt 3 of 3",
+                "attempt 3 failed with code 227, giving up",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn queued() {
+        let store = JobStore::new().await;
+        let mut topics = HashMap::new();
+        topics.insert("backup".to_string(), 1);
+        let executor = Executor::with_limiter(&store, Limiter::new(0, &topics));
+
+        let (gate, wait) = gate("queued");
+        let first = insert(&store, &Job::new("backup", &wait)).await;
+        let second = insert(&store, &Job::new("backup", "echo second")).await;
+        let mut events = store.subscribe();
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            let first = first.clone();
+            async move { executor.run(first).await }
+        });
+        wait_runs(&executor, &first.key, 1).await;
+
+        // the second run waits for the first to give up the topic's permit
+        let waiting = tokio::spawn({
+            let executor = executor.clone();
+            let second = second.clone();
+            async move { executor.run(second).await }
+        });
+        wait_status(&store, &second.key, Status::New(QUEUED)).await;
+        assert!(!waiting.is_finished());
+        assert!(executor.active_runs().is_active(&first.key));
+
+        std::fs::write(&gate, "").unwrap();
+        let model = waiting.await.unwrap();
+        let _ = std::fs::remove_file(&gate);
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(
+            running.await.unwrap().status,
+            Status::Processed(PROCESSED_OK)
+        );
+
+        let mut statuses = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let Some(model) = event.model.filter(|model| model.key == second.key) {
+                statuses.push(model.status);
+            }
+        }
+        assert_eq!(statuses[0], Status::New(QUEUED));
+    }
+
+    async fn overlap_events(
+        store: &JobStore,
+        events: &mut broadcast::Receiver<JobEvent>,
+    ) -> Vec<String> {
+        // the store handles commands in order, so a find reply means the events went out
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Find(String::new(), tx);
+        store.request_channel().send(cmd).await.unwrap();
+        rx.await.unwrap();
+
+        let mut messages = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if event.kind == EventKind::Overlap {
+                messages.push(event.message);
+            }
+        }
+
+        messages
+    }
+
+    #[tokio::test]
+    async fn overlap() {
+        let store = JobStore::new().await;
+        let mut events = store.subscribe();
+        let executor = Executor::new(&store);
+
+        let (gate, wait) = gate("overlap");
+        let mut job = Job::new("overlap", &format!("{}; echo done", wait));
+        job.overlap = OverlapPolicy::Skip;
+        let model = insert(&store, &job).await;
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            let model = model.clone();
+            async move { executor.fire(model).await }
+        });
+        wait_runs(&executor, &model.key, 1).await;
+
+        assert_eq!(executor.fire(model.clone()).await, None);
+        assert_eq!(
+            overlap_events(&store, &mut events).await,
+            vec!["overlap: skipped, the previous run is still active"]
+        );
+
+        // queued behind the first run
+        let mut queued = model.clone();
+        queued.value.overlap = OverlapPolicy::Queue;
+        let queued = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.fire(queued).await }
+        });
+        wait_runs(&executor, &model.key, 2).await;
+        std::fs::write(&gate, "").unwrap();
+        let first = running.await.unwrap().unwrap();
+        let queued = queued.await.unwrap().unwrap();
+        let _ = std::fs::remove_file(&gate);
+        assert_eq!(first.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(queued.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(
+            overlap_events(&store, &mut events).await,
+            vec!["overlap: queued behind the active run"]
+        );
+        assert!(!executor.active_runs().is_active(&model.key));
+    }
+
+    #[tokio::test]
+    async fn parallel() {
+        let store = JobStore::new().await;
+        let runs = RunStore::new().await;
+        let executor = Executor::new(&store).with_run_store(&runs);
+        let (first_gate, wait) = gate("parallel-1");
+        let model = insert(&store, &Job::new("parallel", &format!("echo $$; {}", wait))).await;
+
+        let first = tokio::spawn({
+            let executor = executor.clone();
+            let model = model.clone();
+            async move { executor.fire(model).await }
+        });
+        wait_runs(&executor, &model.key, 1).await;
+
+        // the second run waits on a gate of its own so the first finishes first
+        let (second_gate, wait) = gate("parallel-2");
+        let mut second = model.clone();
+        second.value.action = Action::from(format!("echo $$; {}", wait).as_str());
+        let second = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.fire(second).await }
+        });
+        wait_runs(&executor, &model.key, 2).await;
+        std::fs::write(&first_gate, "").unwrap();
+        let first = first.await.unwrap().unwrap();
+        std::fs::write(&second_gate, "").unwrap();
+        let second = second.await.unwrap().unwrap();
+        let _ = std::fs::remove_file(&first_gate);
+        let _ = std::fs::remove_file(&second_gate);
+
+        // each run keeps its own pid and output
+        assert_ne!(first.value.pid, second.value.pid);
+        assert_ne!(first.value.log, second.value.log);
+        let (tx, rx) = oneshot::channel();
+        let cmd = run_store::Command::List(model.key.to_string(), 0, 10, tx);
+        runs.request_channel().send(cmd).await.unwrap();
+        let list = rx.await.unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].pid, second.value.pid);
+        assert_eq!(list[0].log, second.value.log);
+        assert_eq!(list[1].pid, first.value.pid);
+        assert_eq!(list[1].log, first.value.log);
+
+        // the job shows the latest run once the first is over
+        let current = find(&store, &model.key).await.unwrap();
+        assert_eq!(current.value.run_id, second.value.run_id);
+        assert_eq!(current.value.log, second.value.log);
+    }
+
+    #[tokio::test]
+    async fn overlap_replace() {
+        let store = JobStore::new().await;
+        let executor = Executor::new(&store);
+
+        let mut job = Job::new("overlap", "echo started; sleep 30");
+        job.overlap = OverlapPolicy::Replace;
+        let model = insert(&store, &job).await;
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            let model = model.clone();
+            async move { executor.fire(model).await }
+        });
+        wait_runs(&executor, &model.key, 1).await;
+
+        // the replacement runs the job as edited in the store
+        let mut replacement = model.clone();
+        replacement.value.action = Action::from("echo replaced");
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Insert(Box::new(replacement.clone()), tx);
+        store.request_channel().send(cmd).await.unwrap();
+        rx.await.unwrap().unwrap();
+        let started = Instant::now();
+        let replacement = executor.fire(replacement).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let first = running.await.unwrap().unwrap();
+        assert_eq!(first.status, Status::Processed(FAILED_CANCELLED));
+        assert_eq!(first.value.errors, vec!["cancelled"]);
+        assert_eq!(replacement.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(replacement.value.log, vec!["replaced"]);
+    }
+
+    #[tokio::test]
+    async fn cancel_queued() {
+        let store = JobStore::new().await;
+        let mut topics = HashMap::new();
+        topics.insert("backup".to_string(), 1);
+        let executor = Executor::with_limiter(&store, Limiter::new(0, &topics));
+
+        let mut job = Job::new("backup", "sleep 30");
+        job.overlap = OverlapPolicy::Queue;
+        let first = insert(&store, &job).await;
+        let second = insert(&store, &Job::new("backup", "echo second")).await;
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            let first = first.clone();
+            async move { executor.fire(first).await }
+        });
+        wait_runs(&executor, &first.key, 1).await;
+
+        // a run waiting for the topic's permit ends as soon as it is cancelled
+        let waiting = tokio::spawn({
+            let executor = executor.clone();
+            let second = second.clone();
+            async move { executor.run(second).await }
+        });
+        wait_status(&store, &second.key, Status::New(QUEUED)).await;
+        executor.cancel(&second.key).await.unwrap();
+        let model = waiting.await.unwrap();
+        assert_eq!(model.status, Status::Processed(FAILED_CANCELLED));
+        assert_eq!(model.value.errors, vec!["cancelled"]);
+
+        // a run queued behind the job's active run is dropped with it
+        let queued = tokio::spawn({
+            let executor = executor.clone();
+            let first = first.clone();
+            async move { executor.fire(first).await }
+        });
+        wait_runs(&executor, &first.key, 2).await;
+        executor.cancel(&first.key).await.unwrap();
+        assert_eq!(queued.await.unwrap(), None);
+        let model = running.await.unwrap().unwrap();
+        assert_eq!(model.status, Status::Processed(FAILED_CANCELLED));
+        assert!(!executor.active_runs().is_active(&first.key));
+    }
+
+    /// wait until the job has the number of registered runs and the first has started its process
+    async fn wait_runs(executor: &Executor, key: &str, count: usize) {
+        let started = Instant::now();
+        loop {
+            let runs = executor.active_runs().runs(key);
+            if runs.len() == count && runs[0].pid().is_some() {
+                return;
+            }
+
+            assert!(started.elapsed() < Duration::from_secs(5), "{} runs", count);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// wait for the run to flush the status to the store
+    async fn wait_status(store: &JobStore, key: &str, status: Status) {
+        let started = Instant::now();
+        while find(store, key).await.unwrap().status != status {
+            assert!(started.elapsed() < Duration::from_secs(5), "{:?}", status);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn pause_resume_cancel() {
+        let store = JobStore::new().await;
+        let mut events = store.subscribe();
+        let executor = Executor::new(&store);
+        executor.cancel_removed(&store);
+
+        let model = insert(&store, &Job::new("long", "echo started; sleep 30")).await;
+        let key = model.key.to_string();
+        assert!(executor.pause(&key).await.is_err());
+        assert!(executor.cancel(&key).await.is_err());
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.run(model).await }
+        });
+        wait_runs(&executor, &key, 1).await;
+
+        executor.pause(&key).await.unwrap();
+        assert!(executor.pause(&key).await.is_err());
+        wait_status(&store, &key, Status::Inactive(PAUSED)).await;
+
+        executor.resume(&key).await.unwrap();
+        assert!(executor.resume(&key).await.is_err());
+        wait_status(&store, &key, Status::Active(0)).await;
+
+        // a paused run is continued so it can take the SIGTERM
+        executor.pause(&key).await.unwrap();
+        let started = Instant::now();
+        executor.cancel(&key).await.unwrap();
+        let model = running.await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(model.status, Status::Processed(FAILED_CANCELLED));
+        assert_eq!(model.value.errors, vec!["cancelled"]);
+
+        let mut kinds = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            kinds.push(event.kind);
+        }
+        for kind in [EventKind::Paused, EventKind::Resumed, EventKind::Cancelled] {
+            assert!(kinds.contains(&kind), "missing {:?}", kind);
+        }
+
+        // removing the job from the store cancels its run
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.run(model).await }
+        });
+        wait_runs(&executor, &key, 1).await;
+        store
+            .request_channel()
+            .send(Command::Remove(key.to_string()))
+            .await
+            .unwrap();
+        let model = running.await.unwrap();
+        assert_eq!(model.status, Status::Processed(FAILED_CANCELLED));
+
+        // a removal missed while the events lagged is found by checking the store
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.run(model).await }
+        });
+        wait_runs(&executor, &key, 1).await;
+        executor.discard_missing().await;
+        let model = running.await.unwrap();
+        assert_eq!(model.status, Status::Processed(FAILED_CANCELLED));
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let store = JobStore::new().await;
+        let mut events = store.subscribe();
+        let executor = Executor::new(&store);
+
+        // the shell ignores SIGTERM so it takes the SIGKILL after the grace period
+        let mut job = Job::new("slow", "trap '' TERM; echo waiting; sleep 30");
+        job.timeout_seconds = Some(1);
+        job.kill_grace_seconds = Some(1);
+
+        let started = Instant::now();
+        let model = executor.run(Job::create_model(&job)).await;
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(model.status, Status::Processed(FAILED_TIMEOUT));
+        assert_eq!(model.value.log, vec!["waiting"]);
+        assert_eq!(model.value.errors, vec!["timed out after 1 seconds"]);
+
+        let mut kinds = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            kinds.push(event.kind);
+        }
+        assert!(kinds.contains(&EventKind::TimedOut));
+    }
+
+    #[tokio::test]
+    async fn limits() {
+        let store = JobStore::new().await;
+        let executor = Executor::new(&store);
+
+        let limits = ResourceLimits {
+            open_files: Some(32),
+            ..Default::default()
+        };
+        let mut job = Job::new("limited", "ulimit -n");
+        job.limits = Some(limits);
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.status, Status::Processed(PROCESSED_OK));
+        assert_eq!(model.value.log, vec!["limits: open_files=32", "32"]);
+
+        // a busy loop runs out of cpu time; run in /tmp in case it dumps core
+        let limits = ResourceLimits {
+            cpu_seconds: Some(1),
+            ..Default::default()
+        };
+        let exec = Exec {
+            program: "while :; do :; done".to_string(),
+            shell: true,
+            cwd: Some("/tmp".to_string()),
+            ..Default::default()
+        };
+        let mut job = Job::with_action("limited", Action::Exec(exec));
+        job.limits = Some(limits);
+        job.timeout_seconds = Some(20);
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.status, Status::Processed(FAILED_LIMIT));
+        assert_eq!(model.value.errors, vec!["cpu time limit exceeded"]);
+
+        // a cgroup outside the root is refused before the job does anything
+        let limits = ResourceLimits {
+            cgroup: Some("../escape".to_string()),
+            ..Default::default()
+        };
+        let mut job = Job::new("limited", "sleep 1; echo ran");
+        job.limits = Some(limits);
+        let model = executor.run(Job::create_model(&job)).await;
+        assert_eq!(model.status, Status::Processed(FAILED_TO_START));
+        assert!(model.value.log.is_empty());
+        assert_eq!(
+            model.value.errors,
+            vec!["could not apply limits: invalid cgroup path: ../escape"]
+        );
+    }
+
+    #[test]
+    fn codes() {
+        // a raw wait status holds the exit code in the second byte, or the signal
+        let exited = |code: i32| ExitStatus::from_raw(code << 8);
+        let signaled = ExitStatus::from_raw(libc::SIGKILL);
+        assert_eq!(processed_code(&exited(0)), 0);
+        assert_eq!(processed_code(&exited(1)), 129);
+        assert_eq!(processed_code(&exited(255)), 227);
+        assert_eq!(processed_code(&signaled), FAILED_SIGNALED);
+
+        assert_eq!(exit_code(&exited(3)), Some(3));
+        assert_eq!(exit_code(&exited(137)), Some(137));
+        assert_eq!(exit_code(&signaled), None);
+    }
+}
@@ -0,0 +1,162 @@
+/// FireLog.  The last time each job fired, saved to a json file so the scheduler can catch up
+/// runs that were missed while the service was down.
+///
+/// the file is written by a thread of its own, so recording a fire never blocks the scheduler;
+/// when fires come faster than the writes only the latest times are written, and dropping the log
+/// waits for the last write.
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hashbrown::HashMap;
+use log::{info, warn};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+type FireTimes = HashMap<String, DateTime<Utc>>;
+
+#[derive(Debug, Default)]
+pub struct FireLog {
+    fired: FireTimes,
+    writer: Option<Writer>,
+}
+
+/// Writer - the thread that saves the log and the channel that sends it each new copy
+#[derive(Debug)]
+struct Writer {
+    tx: mpsc::Sender<FireTimes>,
+    thread: JoinHandle<()>,
+}
+
+impl FireLog {
+    /// create an in-memory log that is not saved
+    pub fn new() -> FireLog {
+        FireLog::default()
+    }
+
+    /// create the log saved to the file, reading the fire times already in it
+    pub fn with_file(path: &Path) -> FireLog {
+        let fired = if path.exists() {
+            match FireLog::read_file(path) {
+                Ok(fired) => fired,
+                Err(e) => {
+                    warn!("could not read the fire log {:?}: {}", path, e);
+                    HashMap::new()
+                }
+            }
+        } else {
+            HashMap::new()
+        };
+
+        info!("loaded {} job fire times from {:?}", fired.len(), path);
+
+        let (tx, rx) = mpsc::channel::<FireTimes>();
+        let path = path.to_path_buf();
+        let thread = std::thread::spawn(move || {
+            while let Ok(mut fired) = rx.recv() {
+                // only the latest copy needs to be written
+                while let Ok(newer) = rx.try_recv() {
+                    fired = newer;
+                }
+
+                if let Err(e) = FireLog::write_file(&path, &fired) {
+                    warn!("could not save the fire log {:?}: {}", path, e);
+                }
+            }
+        });
+
+        FireLog {
+            fired,
+            writer: Some(Writer { tx, thread }),
+        }
+    }
+
+    /// return the last time the job fired
+    pub fn last_fired(&self, key: &str) -> Option<DateTime<Utc>> {
+        self.fired.get(key).copied()
+    }
+
+    /// record the job's fire time and save the log
+    pub fn record(&mut self, key: &str, at: &DateTime<Utc>) {
+        self.fired.insert(key.to_string(), *at);
+        self.save();
+    }
+
+    /// forget the job, e.g. when it is removed
+    pub fn remove(&mut self, key: &str) {
+        if self.fired.remove(key).is_some() {
+            self.save();
+        }
+    }
+
+    // hand a copy to the writer thread
+    fn save(&self) {
+        if let Some(writer) = &self.writer {
+            if writer.tx.send(self.fired.clone()).is_err() {
+                warn!("the fire log writer has stopped");
+            }
+        }
+    }
+
+    fn read_file(path: &Path) -> Result<FireTimes> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    // write to a temp file and rename so a crash never leaves a partial file
+    fn write_file(path: &Path, fired: &FireTimes) -> Result<()> {
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+
+        let temp = path.with_extension("tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(serde_json::to_string(fired)?.as_bytes())?;
+        std::fs::rename(&temp, path)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for FireLog {
+    fn drop(&mut self) {
+        // closing the channel ends the thread once it has written the last copy
+        if let Some(Writer { tx, thread }) = self.writer.take() {
+            drop(tx);
+            if thread.join().is_err() {
+                warn!("the fire log writer panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_reload() {
+        let folder = std::env::temp_dir().join(format!("fire-log-{}", std::process::id()));
+        let path = folder.join("last-fired.json");
+        let at = DateTime::parse_from_rfc3339("2022-12-25T08:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let mut log = FireLog::with_file(&path);
+        assert_eq!(log.last_fired("job-1"), None);
+        log.record("job-1", &at);
+        log.record("job-2", &at);
+        log.remove("job-2");
+        drop(log);
+
+        let log = FireLog::with_file(&path);
+        assert_eq!(log.last_fired("job-1"), Some(at));
+        assert_eq!(log.last_fired("job-2"), None);
+
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+}
@@ -1,10 +1,12 @@
 /// JobStore.  A lock-less, thread safe in-memory data store implemented with messaging.
 ///
-// use anyhow::Result;
+use anyhow::Result;
 use log::{error, info};
 // use serde::Serialize;
-use crate::models::jobs::{Job, JobEvent};
-use domain_keys::models::Model;
+use crate::models::jobs::{EventKind, Job, JobEvent};
+use crate::models::runs::RunUpdate;
+use crate::models::workflow::Workflow;
+use domain_keys::models::{Model, Status};
 use hashbrown::HashMap;
 use std::vec::Vec;
 use tokio::sync::broadcast;
@@ -15,10 +17,13 @@ use tokio::sync::oneshot;
 
 #[derive(Debug)]
 pub enum Command {
-    Insert(Box<Model<Job>>, oneshot::Sender<Option<Model<Job>>>),
+    Insert(Box<Model<Job>>, oneshot::Sender<Result<Model<Job>>>), // rejects invalid dependencies
     Find(String, oneshot::Sender<Option<Model<Job>>>),
+    Update(Box<RunUpdate>, oneshot::Sender<Option<Model<Job>>>), // set a run's fields, if it owns the job
+    Block(String, u8, String, oneshot::Sender<Option<Model<Job>>>), // key, code, message: set Blocked(code), add the message to errors
     Remove(String),
     List(usize, usize, oneshot::Sender<Vec<Model<Job>>>), // offset, limit, list (could be empty)
+    Notify(Box<JobEvent>), // broadcast an event from outside the store, e.g. the executor
 }
 
 #[derive(Debug)]
@@ -61,12 +66,26 @@ impl JobStore {
                 match cmd {
                     Command::Insert(model, tx) => {
                         let job = model.as_ref();
-                        map.insert(job.key.to_string(), job.clone());
+                        if let Err(e) = Workflow::check_job(map.values(), job) {
+                            error!("job {} rejected: {}", job.key, e);
+                            let _ = tx.send(Err(e));
+                            continue;
+                        }
+
+                        let event = match map.insert(job.key.to_string(), job.clone()) {
+                            Some(_) => {
+                                JobEvent::new(EventKind::Updated, "job updated", Some(job.clone()))
+                            }
+                            None => JobEvent::new(
+                                EventKind::Inserted,
+                                "job inserted",
+                                Some(job.clone()),
+                            ),
+                        };
 
-                        let _ = tx.send(Some(job.clone()));
+                        let _ = tx.send(Ok(job.clone()));
 
-                        // let event = JobEvent::new(&msg, Some(job.clone()));
-                        // fire(&event_tx, event);
+                        fire(&event_tx, event);
                     }
                     Command::Find(key, tx) => {
                         let _ = if let Some(model) = map.get(&key) {
@@ -75,11 +94,40 @@ impl JobStore {
                             tx.send(None)
                         };
                     }
+                    Command::Update(update, tx) => {
+                        let model = map
+                            .get(&update.key)
+                            .and_then(|current| update.apply(current));
+                        let _ = tx.send(model.clone());
+
+                        if let Some(model) = model {
+                            map.insert(model.key.to_string(), model.clone());
+                            let event =
+                                JobEvent::new(EventKind::Updated, "job updated", Some(model));
+                            fire(&event_tx, event);
+                        }
+                    }
+                    Command::Block(key, code, message, tx) => {
+                        // only the status and errors change, so edits and run updates are kept
+                        let model = map.get(&key).map(|current| {
+                            let mut model = current.clone();
+                            model.value.errors.push(message);
+                            Job::update_model(&model, Status::Blocked(code))
+                        });
+                        let _ = tx.send(model.clone());
+
+                        if let Some(model) = model {
+                            map.insert(model.key.to_string(), model.clone());
+                            let event =
+                                JobEvent::new(EventKind::Updated, "job blocked", Some(model));
+                            fire(&event_tx, event);
+                        }
+                    }
                     Command::Remove(key) => {
                         let event = if let Some(job) = map.remove(&key) {
-                            JobEvent::new("job removed", Some(job))
+                            JobEvent::new(EventKind::Removed, "job removed", Some(job))
                         } else {
-                            JobEvent::new("job not found", None)
+                            JobEvent::new(EventKind::Info, "job not found", None)
                         };
 
                         fire(&event_tx, event);
@@ -94,6 +142,9 @@ impl JobStore {
 
                         // fire(&event_tx, event);
                     }
+                    Command::Notify(event) => {
+                        fire(&event_tx, *event);
+                    }
                 }
 
                 // broadcast the job event
@@ -140,4 +191,110 @@ mod tests {
         let jobs = JobStore::load_jobs("myfile");
         assert_eq!(jobs.len(), 0);
     }
+
+    #[tokio::test]
+    async fn events() {
+        let store = JobStore::new().await;
+        let mut events = store.subscribe();
+        let channel = store.request_channel();
+        let model = Job::create_model(&Job::new("events", "no-op"));
+
+        for _ in 0..2 {
+            let (tx, rx) = oneshot::channel();
+            channel
+                .send(Command::Insert(Box::new(model.clone()), tx))
+                .await
+                .unwrap();
+            assert!(rx.await.unwrap().is_ok());
+        }
+        channel
+            .send(Command::Remove(model.key.to_string()))
+            .await
+            .unwrap();
+        channel
+            .send(Command::Remove(model.key.to_string()))
+            .await
+            .unwrap();
+
+        let kinds = [
+            EventKind::Inserted,
+            EventKind::Updated,
+            EventKind::Removed,
+            EventKind::Info,
+        ];
+        for kind in kinds {
+            assert_eq!(events.recv().await.unwrap().kind, kind);
+        }
+
+        // a run's update to a removed job is dropped
+        let (tx, rx) = oneshot::channel();
+        let update = RunUpdate::new(&model);
+        channel
+            .send(Command::Update(Box::new(update), tx))
+            .await
+            .unwrap();
+        assert_eq!(rx.await.unwrap(), None);
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Block(model.key.to_string(), 1, "blocked".to_string(), tx);
+        channel.send(cmd).await.unwrap();
+        assert_eq!(rx.await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn block() {
+        let store = JobStore::new().await;
+        let channel = store.request_channel();
+        let mut model = Job::create_model(&Job::new("block", "no-op"));
+        model.value.errors.push("earlier".to_string());
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Insert(Box::new(model.clone()), tx);
+        channel.send(cmd).await.unwrap();
+        rx.await.unwrap().unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Block(model.key.to_string(), 1, "blocked".to_string(), tx);
+        channel.send(cmd).await.unwrap();
+        let blocked = rx.await.unwrap().unwrap();
+        assert_eq!(blocked.status, Status::Blocked(1));
+        assert_eq!(blocked.value.errors, vec!["earlier", "blocked"]);
+        assert_eq!(blocked.value.action, model.value.action);
+    }
+
+    #[tokio::test]
+    async fn rejects_cycles() {
+        let store = JobStore::new().await;
+        let channel = store.request_channel();
+        let insert = |model: Model<Job>| {
+            let channel = channel.clone();
+            async move {
+                let (tx, rx) = oneshot::channel();
+                let cmd = Command::Insert(Box::new(model), tx);
+                channel.send(cmd).await.unwrap();
+                rx.await.unwrap()
+            }
+        };
+
+        let first = Job::create_model(&Job::new("first", "no-op"));
+        let mut second = Job::create_model(&Job::new("second", "no-op"));
+        second.value.depends_on = vec![first.key.to_string()];
+        insert(first.clone()).await.unwrap();
+        insert(second.clone()).await.unwrap();
+
+        // the update that would close the cycle is rejected and the job is unchanged
+        let mut looped = first.clone();
+        looped.value.depends_on = vec![second.key.to_string()];
+        let error = insert(looped).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "dependency cycle: {} -> {} -> {}",
+                first.key, second.key, first.key
+            )
+        );
+
+        let (tx, rx) = oneshot::channel();
+        let cmd = Command::Find(first.key.to_string(), tx);
+        channel.send(cmd).await.unwrap();
+        assert!(rx.await.unwrap().unwrap().value.depends_on.is_empty());
+    }
 }
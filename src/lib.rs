@@ -1,11 +1,32 @@
 #![doc = include_str!("../README.md")]
 
+pub mod active_runs;
+pub mod calendar_store;
 pub mod config;
+pub mod executor;
+pub mod fire_log;
 pub mod job_store;
+pub mod limiter;
 pub mod models {
+    pub mod action;
+    pub mod calendar;
+    pub mod cron;
     pub mod jobs;
+    pub mod limits;
+    pub mod progress;
+    pub mod results;
+    pub mod retry;
     pub mod run_at;
+    pub mod runs;
+    pub mod schedule;
+    pub mod workflow;
 }
+pub mod output_spool;
+pub mod process;
+pub mod run_reports;
+pub mod run_store;
+pub mod scheduler;
+pub mod workflow_runner;
 // pub mod session_store;
 
 pub const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
@@ -0,0 +1,111 @@
+/// Limiter.  Caps the number of jobs running at once, globally and per topic, with semaphores;
+/// a run holds a permit from start to finish and runs that can't get one wait in line.
+use hashbrown::HashMap;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug, Clone, Default)]
+pub struct Limiter {
+    global: Option<Arc<Semaphore>>,
+    topics: Arc<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// Permit - the right to run; released when dropped
+#[derive(Debug)]
+pub struct Permit {
+    _topic: Option<OwnedSemaphorePermit>,
+    _global: Option<OwnedSemaphorePermit>,
+}
+
+impl Limiter {
+    /// create the limiter; a limit of 0 means no limit
+    pub fn new(max_concurrent: usize, topic_limits: &HashMap<String, usize>) -> Limiter {
+        let semaphore = |limit: usize| Arc::new(Semaphore::new(limit));
+
+        Limiter {
+            global: Some(max_concurrent).filter(|max| *max > 0).map(semaphore),
+            topics: Arc::new(
+                topic_limits
+                    .iter()
+                    .filter(|(_, limit)| **limit > 0)
+                    .map(|(topic, limit)| (topic.to_string(), semaphore(*limit)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// return a permit if the topic and the global limit both have room now
+    pub fn try_acquire(&self, topic: &str) -> Option<Permit> {
+        // the topic first, so a run waiting on a busy topic never holds a global slot
+        let topic = match self.topics.get(topic) {
+            Some(semaphore) => Some(semaphore.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        let global = match &self.global {
+            Some(semaphore) => Some(semaphore.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+
+        Some(Permit {
+            _topic: topic,
+            _global: global,
+        })
+    }
+
+    /// wait in line for a permit
+    pub async fn acquire(&self, topic: &str) -> Permit {
+        let topic = match self.topics.get(topic) {
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        let global = match &self.global {
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+
+        Permit {
+            _topic: topic,
+            _global: global,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn limits() {
+        let mut topics = HashMap::new();
+        topics.insert("backup".to_string(), 1);
+        topics.insert("report".to_string(), 0);
+        let limiter = Limiter::new(2, &topics);
+
+        let backup = limiter.try_acquire("backup").unwrap();
+        assert!(limiter.try_acquire("backup").is_none());
+
+        let report = limiter.try_acquire("report").unwrap();
+        assert!(limiter.try_acquire("other").is_none());
+
+        drop(report);
+        let other = limiter.try_acquire("other").unwrap();
+
+        // waits until the running backup finishes
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire("backup").await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        drop(backup);
+        drop(other);
+        let _permit = waiting.await.unwrap();
+
+        let unlimited = Limiter::default();
+        let permits: Vec<Permit> = (0..100)
+            .map(|_| unlimited.try_acquire("backup").unwrap())
+            .collect();
+        assert_eq!(permits.len(), 100);
+    }
+}
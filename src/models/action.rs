@@ -0,0 +1,159 @@
+/// Action - what a job runs: a plain command string (run with `sh -c`, the original format) or a
+/// structured program with an exact argument vector, working directory and environment.
+///
+/// serialized untagged so the original `"action": "backup.sh --all"` string is still accepted.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Action {
+    /// a command line run through the shell, e.g. "backup.sh --all"
+    Command(String),
+    /// a program and its arguments, passed to the process as is
+    Exec(Exec),
+}
+
+/// Exec - a program with its arguments; with `shell` the program is a script for `sh -c` and the
+/// args are its positional parameters ($1, $2, ...)
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Exec {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub shell: bool,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Action {
+    /// create a structured action from the program and args
+    pub fn exec(program: &str, args: &[&str]) -> Action {
+        Action::Exec(Exec {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    /// return the argument vector for the process; the first item is the program
+    pub fn argv(&self) -> Result<Vec<String>> {
+        let sh = |script: &str| vec!["sh".to_string(), "-c".to_string(), script.to_string()];
+
+        match self {
+            Action::Command(command) if command.trim().is_empty() => {
+                Err(anyhow!("the action is empty"))
+            }
+            Action::Command(command) => Ok(sh(command)),
+            Action::Exec(exec) if exec.program.trim().is_empty() => {
+                Err(anyhow!("the action program is empty"))
+            }
+            Action::Exec(exec) if exec.shell => {
+                // $0 is the name sh reports in errors, the args follow as $1, $2, ...
+                let mut argv = sh(&exec.program);
+                argv.push("sh".to_string());
+                argv.extend(exec.args.iter().cloned());
+
+                Ok(argv)
+            }
+            Action::Exec(exec) => {
+                let mut argv = vec![exec.program.to_string()];
+                argv.extend(exec.args.iter().cloned());
+
+                Ok(argv)
+            }
+        }
+    }
+
+    /// return the working directory, if set
+    pub fn cwd(&self) -> Option<&str> {
+        match self {
+            Action::Exec(exec) => exec.cwd.as_deref(),
+            Action::Command(_) => None,
+        }
+    }
+
+    /// return the environment variables added to the service's environment
+    pub fn env(&self) -> Vec<(String, String)> {
+        match self {
+            Action::Exec(exec) => exec
+                .env
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            Action::Command(_) => Vec::new(),
+        }
+    }
+}
+
+impl Default for Action {
+    fn default() -> Action {
+        Action::Command(String::new())
+    }
+}
+
+impl From<&str> for Action {
+    fn from(command: &str) -> Action {
+        Action::Command(command.to_string())
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Command(command) => write!(f, "{}", command),
+            Action::Exec(exec) => write!(f, "{} {:?}", exec.program, exec.args),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argv() {
+        let action = Action::from("backup.sh --all");
+        assert_eq!(action.argv().unwrap(), vec!["sh", "-c", "backup.sh --all"]);
+
+        let action = Action::exec("rsync", &["-a", "my files/", "host:'backup'"]);
+        assert_eq!(
+            action.argv().unwrap(),
+            vec!["rsync", "-a", "my files/", "host:'backup'"]
+        );
+
+        let action = Action::Exec(Exec {
+            program: "echo \"$1\"".to_string(),
+            args: vec!["a b".to_string()],
+            shell: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            action.argv().unwrap(),
+            vec!["sh", "-c", "echo \"$1\"", "sh", "a b"]
+        );
+
+        assert!(Action::from(" ").argv().is_err());
+        assert!(Action::exec("", &[]).argv().is_err());
+    }
+
+    #[test]
+    fn serialize() {
+        let action: Action = serde_json::from_str(r#""backup.sh --all""#).unwrap();
+        assert_eq!(action, Action::from("backup.sh --all"));
+
+        let json = r#"{"program":"pg_dump","args":["-f","/tmp/db.sql"],"cwd":"/tmp","env":{"PGHOST":"db"}}"#;
+        let action: Action = serde_json::from_str(json).unwrap();
+        assert_eq!(action.cwd(), Some("/tmp"));
+        assert_eq!(action.env(), vec![("PGHOST".to_string(), "db".to_string())]);
+        assert_eq!(
+            serde_json::to_string(&action).unwrap(),
+            r#"{"program":"pg_dump","args":["-f","/tmp/db.sql"],"shell":false,"cwd":"/tmp","env":{"PGHOST":"db"}}"#
+        );
+    }
+}
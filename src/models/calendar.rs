@@ -0,0 +1,197 @@
+/// Calendar - a named list of excluded dates (holidays, change freezes) applied to job schedules.
+///
+/// jobs reference a calendar by name; scheduled runs that fall on an excluded date are skipped.
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+/// Exclusion - a single date or an inclusive range of dates
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Exclusion {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl Exclusion {
+    /// return true if the date is between start and end inclusive
+    pub fn contains(&self, date: &NaiveDate) -> bool {
+        *date >= self.start && *date <= self.end
+    }
+}
+
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Calendar {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub exclusions: Vec<Exclusion>,
+}
+
+impl Calendar {
+    /// create a new empty calendar
+    pub fn new(name: &str) -> Calendar {
+        Calendar {
+            name: name.to_string(),
+            description: String::new(),
+            exclusions: Vec::new(),
+        }
+    }
+
+    /// exclude a single date
+    pub fn exclude_date(&mut self, date: NaiveDate, description: &str) {
+        self.exclude_range(date, date, description);
+    }
+
+    /// exclude the dates from start to end inclusive
+    pub fn exclude_range(&mut self, start: NaiveDate, end: NaiveDate, description: &str) {
+        self.exclusions.push(Exclusion {
+            start,
+            end,
+            description: description.to_string(),
+        });
+    }
+
+    /// return true if the date is excluded
+    pub fn excludes(&self, date: &NaiveDate) -> bool {
+        self.exclusions.iter().any(|ex| ex.contains(date))
+    }
+
+    /// if the date is excluded return the last date of the excluded span that contains it,
+    /// following overlapping and adjacent exclusions; None if the date is not excluded.
+    pub fn excluded_through(&self, date: &NaiveDate) -> Option<NaiveDate> {
+        let mut last: Option<NaiveDate> = None;
+        let mut day = *date;
+
+        while let Some(end) = self
+            .exclusions
+            .iter()
+            .filter(|ex| ex.contains(&day))
+            .map(|ex| ex.end)
+            .max()
+        {
+            last = Some(end);
+            day = end.succ_opt()?;
+        }
+
+        last
+    }
+
+    /// calendar names become file names, so only allow letters, digits, - and _
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    /// return an error if the name is not usable as a file name or an exclusion is backwards
+    pub fn validate(&self) -> Result<()> {
+        if !Calendar::is_valid_name(&self.name) {
+            return Err(anyhow!("invalid calendar name: '{}'", self.name));
+        }
+
+        if let Some(ex) = self.exclusions.iter().find(|ex| ex.start > ex.end) {
+            return Err(anyhow!(
+                "calendar {}: exclusion {} to {} is backwards",
+                self.name,
+                ex.start,
+                ex.end
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// read a calendar from a json file
+    pub fn read_file(path: &Path) -> Result<Calendar> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(file);
+
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let calendar: Calendar = serde_json::from_str(&text)?;
+        calendar.validate()?;
+
+        info!("read calendar: {} from {:?}", calendar.name, path);
+
+        Ok(calendar)
+    }
+
+    /// write the calendar as json to the folder, named <name>.json
+    pub fn write_file(&self, folder: &Path) -> Result<()> {
+        self.validate()?;
+        std::fs::create_dir_all(folder)?;
+
+        let path = folder.join(format!("{}.json", self.name));
+        let mut file = File::create(&path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+
+        info!("wrote calendar: {} to {:?}", self.name, path);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn excludes() {
+        let mut calendar = Calendar::new("us-holidays");
+        calendar.exclude_date(date("2022-12-25"), "christmas");
+        calendar.exclude_range(date("2022-12-30"), date("2023-01-02"), "new year");
+
+        assert!(calendar.excludes(&date("2022-12-25")));
+        assert!(!calendar.excludes(&date("2022-12-26")));
+        assert!(calendar.excludes(&date("2022-12-31")));
+        assert!(calendar.excludes(&date("2023-01-02")));
+        assert!(!calendar.excludes(&date("2023-01-03")));
+    }
+
+    #[test]
+    fn excluded_through() {
+        let mut calendar = Calendar::new("change-freeze-q4");
+        calendar.exclude_range(date("2022-12-01"), date("2022-12-10"), "");
+        calendar.exclude_range(date("2022-12-05"), date("2022-12-15"), "");
+        calendar.exclude_date(date("2022-12-16"), "");
+
+        assert_eq!(
+            calendar.excluded_through(&date("2022-12-02")),
+            Some(date("2022-12-16"))
+        );
+        assert_eq!(calendar.excluded_through(&date("2022-12-17")), None);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(Calendar::new("us-holidays").validate().is_ok());
+        assert!(Calendar::new("../etc/passwd").validate().is_err());
+        assert!(Calendar::new("").validate().is_err());
+
+        let mut calendar = Calendar::new("backwards");
+        calendar.exclude_range(date("2022-12-10"), date("2022-12-01"), "");
+        assert!(calendar.validate().is_err());
+    }
+
+    #[test]
+    fn serialize() {
+        let json =
+            r#"{"name":"us-holidays","exclusions":[{"start":"2022-12-25","end":"2022-12-25"}]}"#;
+        let calendar: Calendar = serde_json::from_str(json).unwrap();
+
+        assert_eq!(calendar.name, "us-holidays");
+        assert!(calendar.excludes(&date("2022-12-25")));
+    }
+}
@@ -0,0 +1,591 @@
+/// Cron - parse standard five field cron expressions into RunAt structs.
+///
+/// fields are: minute hour day-of-month month day-of-week; see `man 5 crontab` for definitions.
+/// a sixth leading field may be used for seconds: second minute hour day-of-month month day-of-week.
+/// supports `*`, lists (1,2,3), ranges (1-5), steps (*/15, 0-30/10, 5/15) and the names jan..dec
+/// and sun..sat.  The @yearly, @monthly, @weekly, @daily and @hourly shortcuts are also accepted.
+///
+/// the quartz extensions are supported too: L, L-n, LW and nW in the day-of-month field, nL and d#n
+/// in the day-of-week field.
+use crate::models::run_at::{DayRule, RunAt};
+use anyhow::{anyhow, Result};
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// the rules for a single cron field
+struct Field {
+    name: &'static str,
+    min: u16,
+    max: u16,
+    names: &'static [&'static str],
+    first_name: u16, // the value of names[0]
+}
+
+const SECOND: Field = Field {
+    name: "second",
+    min: 0,
+    max: 59,
+    names: &[],
+    first_name: 0,
+};
+
+const MINUTE: Field = Field {
+    name: "minute",
+    min: 0,
+    max: 59,
+    names: &[],
+    first_name: 0,
+};
+
+const HOUR: Field = Field {
+    name: "hour",
+    min: 0,
+    max: 23,
+    names: &[],
+    first_name: 0,
+};
+
+const DAY_OF_MONTH: Field = Field {
+    name: "day-of-month",
+    min: 1,
+    max: 31,
+    names: &[],
+    first_name: 0,
+};
+
+const MONTH: Field = Field {
+    name: "month",
+    min: 1,
+    max: 12,
+    names: &MONTH_NAMES,
+    first_name: 1,
+};
+
+const DAY_OF_WEEK: Field = Field {
+    name: "day-of-week",
+    min: 0,
+    max: 7,
+    names: &DAY_NAMES,
+    first_name: 0,
+};
+
+const YEAR: Field = Field {
+    name: "year",
+    min: 1970,
+    max: 2999,
+    names: &[],
+    first_name: 0,
+};
+
+/// parse a five field cron expression (or an @ shortcut) into a RunAt.  six fields add leading
+/// seconds, seven fields add leading seconds and trailing years.  the expression may start with
+/// `CRON_TZ=<zone>` (or `TZ=<zone>`) to set the time zone.
+///
+/// as in crontab, when both day-of-month and day-of-week are restricted the RunAt matches days that
+/// satisfy either field, e.g. "0 0 13 * 5" runs on the 13th and on every friday.
+pub fn parse(expr: &str) -> Result<RunAt> {
+    let expr = expr.trim();
+    let (timezone, body) = match expr.split_once(char::is_whitespace) {
+        Some((first, rest)) if first.starts_with("CRON_TZ=") || first.starts_with("TZ=") => {
+            let (_, zone) = first.split_once('=').unwrap_or_default();
+            (Some(zone.to_string()), rest.trim())
+        }
+        _ => (None, expr),
+    };
+
+    let expanded = match body.to_lowercase().as_str() {
+        "@yearly" | "@annually" => "0 0 1 1 *",
+        "@monthly" => "0 0 1 * *",
+        "@weekly" => "0 0 * * 0",
+        "@daily" | "@midnight" => "0 0 * * *",
+        "@hourly" => "0 * * * *",
+        _ if body.starts_with('@') => {
+            return Err(anyhow!("cron expression '{}': unknown shortcut", expr));
+        }
+        _ => body,
+    };
+
+    let mut fields: Vec<&str> = expanded.split_whitespace().collect();
+    let mut at = RunAt::new();
+
+    if let Some(zone) = timezone {
+        at.timezone = Some(zone);
+        if let Err(e) = at.time_zone() {
+            return Err(anyhow!("cron expression '{}': {}", expr, e));
+        }
+    }
+
+    match fields.len() {
+        5 => (),
+        6 | 7 => {
+            if fields.len() == 7 {
+                at.years = parse_field(expr, 7, fields[6], &YEAR)?;
+                fields.truncate(6);
+            }
+            at.seconds = Some(narrow(parse_field(expr, 1, fields[0], &SECOND)?));
+            fields.remove(0);
+        }
+        n => {
+            return Err(anyhow!(
+                "cron expression '{}': expected 5 fields (minute hour day-of-month month day-of-week), 6 with leading seconds or 7 with trailing years, found {}",
+                expr,
+                n
+            ))
+        }
+    }
+
+    // field positions are reported as written, so shift them when seconds are present
+    let offset = at.seconds.is_some() as usize;
+    at.minutes = narrow(parse_field(expr, offset + 1, fields[0], &MINUTE)?);
+    at.hours = narrow(parse_field(expr, offset + 2, fields[1], &HOUR)?);
+    let (rules, rest) = split_rules(expr, offset + 3, fields[2], &DAY_OF_MONTH)?;
+    at.day_rules.extend(rules);
+    at.days_of_month = narrow(parse_field(expr, offset + 3, &rest, &DAY_OF_MONTH)?);
+    at.months = narrow(parse_field(expr, offset + 4, fields[3], &MONTH)?);
+
+    // sunday is either 0 or 7; store it as 0
+    let (rules, rest) = split_rules(expr, offset + 5, fields[4], &DAY_OF_WEEK)?;
+    at.day_rules.extend(rules);
+    let mut days_of_week: Vec<u8> = parse_field(expr, offset + 5, &rest, &DAY_OF_WEEK)?
+        .iter()
+        .map(|day| (day % 7) as u8)
+        .collect();
+    days_of_week.sort_unstable();
+    days_of_week.dedup();
+    at.days_of_week = days_of_week;
+
+    Ok(at)
+}
+
+/// format a RunAt as a cron expression that parses back to the same schedule.  seconds are
+/// included when set (or as 0 when there are years), years as a seventh field and the time zone as
+/// a `CRON_TZ=` prefix.
+pub fn format(at: &RunAt) -> String {
+    let mut fields: Vec<String> = Vec::new();
+    let with_years = !at.years.is_empty();
+
+    match &at.seconds {
+        Some(seconds) => fields.push(format_list(seconds)),
+        None if with_years => fields.push("0".to_string()),
+        None => (),
+    }
+
+    let (dom_rules, dow_rules): (Vec<&DayRule>, Vec<&DayRule>) =
+        at.day_rules.iter().partition(|rule| rule.is_day_of_month());
+
+    fields.push(format_list(&at.minutes));
+    fields.push(format_list(&at.hours));
+    fields.push(format_day_field(&at.days_of_month, &dom_rules));
+    fields.push(format_list(&at.months));
+    fields.push(format_day_field(&at.days_of_week, &dow_rules));
+
+    if with_years {
+        fields.push(format_list(&at.years));
+    }
+
+    match &at.timezone {
+        Some(zone) => format!("CRON_TZ={} {}", zone, fields.join(" ")),
+        None => fields.join(" "),
+    }
+}
+
+/// format the values as a cron list; runs of three or more become ranges, empty becomes *
+fn format_list<T: Into<u16> + Copy>(values: &[T]) -> String {
+    if values.is_empty() {
+        return "*".to_string();
+    }
+
+    let mut sorted: Vec<u16> = values.iter().map(|v| (*v).into()).collect();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut items: Vec<String> = Vec::new();
+    let mut idx = 0;
+    while idx < sorted.len() {
+        let mut end = idx;
+        while end + 1 < sorted.len() && sorted[end + 1] == sorted[end] + 1 {
+            end += 1;
+        }
+
+        if end - idx >= 2 {
+            items.push(format!("{}-{}", sorted[idx], sorted[end]));
+        } else {
+            items.extend(sorted[idx..=end].iter().map(|v| v.to_string()));
+        }
+
+        idx = end + 1;
+    }
+
+    items.join(",")
+}
+
+/// format a day field: the plain days followed by any rules
+fn format_day_field(days: &[u8], rules: &[&DayRule]) -> String {
+    if rules.is_empty() {
+        return format_list(days);
+    }
+
+    let mut items: Vec<String> = Vec::new();
+    if !days.is_empty() {
+        items.push(format_list(days));
+    }
+
+    for rule in rules {
+        items.push(match rule {
+            DayRule::LastDayOfMonth(0) => "L".to_string(),
+            DayRule::LastDayOfMonth(offset) => format!("L-{}", offset),
+            DayRule::LastWeekdayOfMonth => "LW".to_string(),
+            DayRule::NearestWeekday(day) => format!("{}W", day),
+            DayRule::LastDayOfWeek(day) => format!("{}L", day),
+            DayRule::NthDayOfWeek { day, nth } => format!("{}#{}", day, nth),
+        });
+    }
+
+    items.join(",")
+}
+
+/// pull the L, W and # items out of a day field; returns the rules and the remaining list items,
+/// which may be empty (no plain days).
+fn split_rules(
+    expr: &str,
+    position: usize,
+    text: &str,
+    field: &Field,
+) -> Result<(Vec<DayRule>, String)> {
+    let mut rules: Vec<DayRule> = Vec::new();
+    let mut rest: Vec<&str> = Vec::new();
+
+    for part in text.split(',') {
+        let upper = part.to_uppercase();
+        let is_rule = if field.names.is_empty() {
+            upper.starts_with('L') || upper.ends_with('W')
+        } else {
+            upper.contains('#') || (upper.len() > 1 && upper.ends_with('L'))
+        };
+
+        if !is_rule {
+            rest.push(part);
+            continue;
+        }
+
+        match parse_rule(&upper, field) {
+            Some(rule) => rules.push(rule),
+            None => {
+                return Err(anyhow!(
+                    "cron expression '{}': field {} ({}) '{}': '{}' is not a valid rule",
+                    expr,
+                    position,
+                    field.name,
+                    text,
+                    part
+                ))
+            }
+        }
+    }
+
+    let rest = if rest.is_empty() {
+        "*".to_string()
+    } else {
+        rest.join(",")
+    };
+
+    Ok((rules, rest))
+}
+
+/// parse a single upper case rule; day of month fields have no names
+fn parse_rule(rule: &str, field: &Field) -> Option<DayRule> {
+    if field.names.is_empty() {
+        match rule {
+            "L" => Some(DayRule::LastDayOfMonth(0)),
+            "LW" => Some(DayRule::LastWeekdayOfMonth),
+            _ => match rule.strip_prefix("L-") {
+                Some(offset) => offset
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|v| *v <= 30)
+                    .map(DayRule::LastDayOfMonth),
+                None => parse_value(rule.strip_suffix('W')?, field)
+                    .map(|day| DayRule::NearestWeekday(day as u8)),
+            },
+        }
+    } else if let Some((day, nth)) = rule.split_once('#') {
+        let day = (parse_value(day, field)? % 7) as u8;
+        let nth = nth.parse::<u8>().ok().filter(|v| (1..=5).contains(v))?;
+        Some(DayRule::NthDayOfWeek { day, nth })
+    } else {
+        let day = (parse_value(rule.strip_suffix('L')?, field)? % 7) as u8;
+        Some(DayRule::LastDayOfWeek(day))
+    }
+}
+
+/// parse a single field into a sorted list of values; `*` returns an empty list (any value).
+fn parse_field(expr: &str, position: usize, text: &str, field: &Field) -> Result<Vec<u16>> {
+    let error = |msg: String| {
+        anyhow!(
+            "cron expression '{}': field {} ({}) '{}': {}",
+            expr,
+            position,
+            field.name,
+            text,
+            msg
+        )
+    };
+
+    if text == "*" || text == "?" {
+        return Ok(Vec::new());
+    }
+
+    let mut values: Vec<u16> = Vec::new();
+    for part in text.split(',') {
+        if part.is_empty() {
+            return Err(error("empty list item".to_string()));
+        }
+
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u16>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(error(format!("invalid step '{}'", step))),
+            },
+            None => (part, None),
+        };
+
+        let (low, high) = if range == "*" {
+            (Some(field.min), Some(field.max))
+        } else if let Some((low, high)) = range.split_once('-') {
+            (parse_value(low, field), parse_value(high, field))
+        } else {
+            let value = parse_value(range, field);
+            match (value, step) {
+                // 5/15 means starting at 5, every 15 to the end of the range
+                (Some(v), Some(_)) => (Some(v), Some(field.max)),
+                _ => (value, value),
+            }
+        };
+
+        let (low, high) = match (low, high) {
+            (Some(low), Some(high)) => (low, high),
+            _ => {
+                return Err(error(format!(
+                    "'{}' is not a valid value; expected {}..{}{}",
+                    range,
+                    field.min,
+                    field.max,
+                    if field.names.is_empty() {
+                        ""
+                    } else {
+                        " or a name"
+                    }
+                )))
+            }
+        };
+
+        if low > high {
+            return Err(error(format!("range {}-{} is backwards", low, high)));
+        }
+
+        values.extend((low..=high).step_by(step.unwrap_or(1) as usize));
+    }
+
+    values.sort_unstable();
+    values.dedup();
+
+    Ok(values)
+}
+
+/// parse a number or a name; returns None if the value is unknown or out of range
+fn parse_value(text: &str, field: &Field) -> Option<u16> {
+    let lower = text.to_lowercase();
+    let value = match field.names.iter().position(|name| *name == lower) {
+        Some(idx) => idx as u16 + field.first_name,
+        None => text.parse::<u16>().ok()?,
+    };
+
+    if value >= field.min && value <= field.max {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// field values other than years fit in a u8
+fn narrow(values: Vec<u16>) -> Vec<u8> {
+    values.iter().map(|v| *v as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_every_quarter_hour_on_weekdays() {
+        let at = parse("*/15 9-17 * * 1-5").unwrap();
+
+        assert_eq!(at.minutes, vec![0u8, 15u8, 30u8, 45u8]);
+        assert_eq!(at.hours, (9u8..=17u8).collect::<Vec<u8>>());
+        assert!(at.days_of_month.is_empty());
+        assert!(at.months.is_empty());
+        assert_eq!(at.days_of_week, vec![1u8, 2u8, 3u8, 4u8, 5u8]);
+        assert!(at.years.is_empty());
+    }
+
+    #[test]
+    fn parse_lists_steps_and_names() {
+        let at = parse("5,10/20 0 1,15 jan-mar,DEC sun,sat").unwrap();
+
+        assert_eq!(at.minutes, vec![5u8, 10u8, 30u8, 50u8]);
+        assert_eq!(at.hours, vec![0u8]);
+        assert_eq!(at.days_of_month, vec![1u8, 15u8]);
+        assert_eq!(at.months, vec![1u8, 2u8, 3u8, 12u8]);
+        assert_eq!(at.days_of_week, vec![0u8, 6u8]);
+    }
+
+    #[test]
+    fn parse_sunday_as_seven() {
+        let at = parse("0 0 * * 5-7").unwrap();
+        assert_eq!(at.days_of_week, vec![0u8, 5u8, 6u8]);
+    }
+
+    #[test]
+    fn parse_with_seconds() {
+        let at = parse("*/15 * * * * *").unwrap();
+        assert_eq!(at.seconds, Some(vec![0u8, 15u8, 30u8, 45u8]));
+        assert!(at.minutes.is_empty());
+
+        let at = parse("* 0 * * * *").unwrap();
+        assert_eq!(at.seconds, Some(Vec::new()));
+        assert_eq!(at.minutes, vec![0u8]);
+
+        let at = parse("0 * * * *").unwrap();
+        assert_eq!(at.seconds, None);
+
+        let err = parse("0 60 * * * *").unwrap_err().to_string();
+        assert!(err.contains("field 2 (minute) '60'"), "{}", err);
+
+        let err = parse("61 0 * * * *").unwrap_err().to_string();
+        assert!(err.contains("field 1 (second) '61'"), "{}", err);
+    }
+
+    #[test]
+    fn parse_day_rules() {
+        let at = parse("0 17 L * *").unwrap();
+        assert_eq!(at.day_rules, vec![DayRule::LastDayOfMonth(0)]);
+        assert!(at.days_of_month.is_empty());
+
+        let at = parse("0 17 1,15W,L-2,LW * *").unwrap();
+        assert_eq!(at.days_of_month, vec![1u8]);
+        assert_eq!(
+            at.day_rules,
+            vec![
+                DayRule::NearestWeekday(15),
+                DayRule::LastDayOfMonth(2),
+                DayRule::LastWeekdayOfMonth
+            ]
+        );
+
+        let at = parse("0 17 * * 5L,tue#2").unwrap();
+        assert!(at.days_of_week.is_empty());
+        assert_eq!(
+            at.day_rules,
+            vec![
+                DayRule::LastDayOfWeek(5),
+                DayRule::NthDayOfWeek { day: 2, nth: 2 }
+            ]
+        );
+
+        let at = parse("0 17 * * FRIL,0").unwrap();
+        assert_eq!(at.day_rules, vec![DayRule::LastDayOfWeek(5)]);
+        assert_eq!(at.days_of_week, vec![0u8]);
+
+        let err = parse("0 17 32W * *").unwrap_err().to_string();
+        assert!(err.contains("field 3 (day-of-month)"), "{}", err);
+        assert!(err.contains("'32W' is not a valid rule"), "{}", err);
+
+        let err = parse("0 17 * * 2#6").unwrap_err().to_string();
+        assert!(err.contains("field 5 (day-of-week)"), "{}", err);
+    }
+
+    #[test]
+    fn parse_time_zone_and_years() {
+        let at = parse("CRON_TZ=America/Los_Angeles 0 9 * * *").unwrap();
+        assert_eq!(at.timezone, Some("America/Los_Angeles".to_string()));
+        assert_eq!(at.hours, vec![9u8]);
+
+        let at = parse("TZ=UTC @daily").unwrap();
+        assert_eq!(at.timezone, Some("UTC".to_string()));
+
+        let err = parse("CRON_TZ=Nowhere/Special 0 9 * * *")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown time zone"), "{}", err);
+
+        let at = parse("0 0 12 1 1 * 2023-2025,2030").unwrap();
+        assert_eq!(at.seconds, Some(vec![0u8]));
+        assert_eq!(at.years, vec![2023u16, 2024u16, 2025u16, 2030u16]);
+
+        let err = parse("0 0 12 1 1 * 1900").unwrap_err().to_string();
+        assert!(err.contains("field 7 (year) '1900'"), "{}", err);
+    }
+
+    #[test]
+    fn format_round_trip() {
+        for expr in [
+            "*/15 9-17 * * 1-5",
+            "0 0 1,15 jan,mar *",
+            "30 2 L,1 * *",
+            "0 17 * * 5L,2#2",
+            "0 17 15W,LW * 0,6",
+            "*/10 * * * * *",
+            "CRON_TZ=America/Los_Angeles 0 9 * * *",
+            "0 0 12 1 1 * 2023-2025,2030",
+        ] {
+            let at = parse(expr).unwrap();
+            let text = format(&at);
+            assert_eq!(parse(&text).unwrap(), at, "{} => {}", expr, text);
+        }
+
+        assert_eq!(
+            format(&parse("*/15 9-17 * * 1-5").unwrap()),
+            "0,15,30,45 9-17 * * 1-5"
+        );
+        assert_eq!(format(&parse("0 17 1,L * *").unwrap()), "0 17 1,L * *");
+        assert_eq!(format(&RunAt::new()), "* * * * *");
+    }
+
+    #[test]
+    fn parse_shortcuts() {
+        let at = parse("@daily").unwrap();
+        assert_eq!(at.minutes, vec![0u8]);
+        assert_eq!(at.hours, vec![0u8]);
+        assert!(at.days_of_week.is_empty());
+
+        let at = parse("@weekly").unwrap();
+        assert_eq!(at.days_of_week, vec![0u8]);
+
+        assert!(parse("@sometimes").is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = parse("0 0 * *").unwrap_err().to_string();
+        assert!(err.contains("expected 5 fields"), "{}", err);
+
+        let err = parse("0 25 * * *").unwrap_err().to_string();
+        assert!(err.contains("field 2 (hour) '25'"), "{}", err);
+
+        let err = parse("0 0 * foo *").unwrap_err().to_string();
+        assert!(err.contains("field 4 (month) 'foo'"), "{}", err);
+
+        let err = parse("*/0 0 * * *").unwrap_err().to_string();
+        assert!(err.contains("invalid step '0'"), "{}", err);
+
+        let err = parse("0 0 20-10 * *").unwrap_err().to_string();
+        assert!(err.contains("field 3 (day-of-month)"), "{}", err);
+        assert!(err.contains("backwards"), "{}", err);
+
+        let err = parse("0 0 1,,2 * *").unwrap_err().to_string();
+        assert!(err.contains("empty list item"), "{}", err);
+    }
+}
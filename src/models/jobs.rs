@@ -1,27 +1,83 @@
+use crate::models::action::Action;
+use crate::models::calendar::Calendar;
+use crate::models::limits::ResourceLimits;
+use crate::models::results::Results;
+use crate::models::retry::RetryPolicy;
 use crate::models::run_at::RunAt;
+use crate::models::schedule::Schedule;
+use chrono::{DateTime, Duration, Utc};
 /// Job models
 ///
 // use anyhow::Result;
 use domain_keys::{
     keys::RouteKey, keys::TimeStampKey, models::Model, models::Status, models::Version,
 };
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// the most scheduled times checked when looking for missed runs
+const MAX_MISSED_SCAN: usize = 100_000;
 
 // use domain_keys::models::Model;
 
+/// EventKind - what happened to the job; lets subscribers react without parsing the message
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    #[default]
+    Info,
+    Inserted,
+    Updated,
+    Removed,
+    TimedOut,
+    Overlap,
+    Completed,
+    Cancelled,
+    Paused,
+    Resumed,
+    Progress,
+}
+
+/// OverlapPolicy - what to do when a job fires while its previous run is still active
+#[derive(
+    Debug, Default, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapPolicy {
+    Skip,
+    Queue,
+    #[default]
+    Parallel,
+    Replace,
+}
+
+/// MisfirePolicy - what to do at startup with runs missed while the service was down
+#[derive(
+    Debug, Default, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MisfirePolicy {
+    #[default]
+    Ignore,
+    RunOnce,
+    RunAll(u32), // run each missed time, up to the most recent n
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct JobEvent {
     pub mid: String,
+    #[serde(default)]
+    pub kind: EventKind,
     pub message: String,
     pub model: Option<Model<Job>>,
 }
 
 impl JobEvent {
     /// create a new job
-    pub fn new(message: &str, model: Option<Model<Job>>) -> JobEvent {
+    pub fn new(kind: EventKind, message: &str, model: Option<Model<Job>>) -> JobEvent {
         JobEvent {
             mid: TimeStampKey::create(),
+            kind,
             message: message.to_string(),
             model,
         }
@@ -33,10 +89,32 @@ impl JobEvent {
 pub struct Job {
     pub topic: String,
     pub description: String,
-    pub run_at: Option<RunAt>,
-    pub action: String, // an OS Exec command with params
+    pub run_at: Option<Schedule>,
+    #[serde(default)]
+    pub calendar: Option<String>, // the name of a calendar of excluded dates
+    #[serde(default)]
+    pub depends_on: Vec<String>, // keys of jobs that must succeed before this job runs
+    pub action: Action, // an OS Exec command with params, or a program with an args vector
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>, // terminate the process group when the run takes longer
+    #[serde(default)]
+    pub kill_grace_seconds: Option<u64>, // wait between SIGTERM and SIGKILL, default 10
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>, // rlimits, cgroup and the user the process runs as
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>, // retry failed runs with backoff, Blocked when attempts run out
+    #[serde(default)]
+    pub overlap: OverlapPolicy, // when the job fires while a run is still active
+    #[serde(default)]
+    pub misfire: MisfirePolicy, // catch up runs missed while the service was down
+    #[serde(default)]
+    pub results_file: Option<String>, // json results written by the job, relative to its cwd
+    #[serde(default)]
+    pub run_id: Option<String>, // the current or latest run; past runs are in the RunStore
     pub pid: Option<u64>,
-    pub results: Option<String>, // could be a simple string, comma delimited list, or json blob (why not Any?)
+    pub results: Option<Results>, // json from the results file or the marked stdout line
+    #[serde(default)]
+    pub progress: Option<String>, // the message of the latest progress report
     pub log: Vec<String>,
     pub errors: Vec<String>,
 }
@@ -48,22 +126,111 @@ impl Job {
             topic: topic.to_string(),
             description: String::new(),
             run_at: None,
-            action: action.to_string(),
+            calendar: None,
+            depends_on: Vec::new(),
+            action: Action::from(action),
+            timeout_seconds: None,
+            kill_grace_seconds: None,
+            limits: None,
+            retry: None,
+            overlap: OverlapPolicy::default(),
+            misfire: MisfirePolicy::default(),
+            results_file: None,
+            run_id: None,
             pid: None,
             results: None,
+            progress: None,
             log: Vec::new(),
             errors: Vec::new(),
         }
     }
 
+    /// create the job with topic and a structured action
+    pub fn with_action(topic: &str, action: Action) -> Job {
+        let mut job = Job::new(topic, "");
+        job.action = action;
+
+        job
+    }
+
     /// create the job with topic, action and a run at time definition
     pub fn with_run_at(topic: &str, action: &str, run_at: RunAt) -> Job {
+        Job::with_schedule(topic, action, Schedule::Cron(run_at))
+    }
+
+    /// create the job with topic, action and any schedule: cron, once, interval or delay
+    pub fn with_schedule(topic: &str, action: &str, schedule: Schedule) -> Job {
         let mut job = Job::new(topic, action);
-        job.run_at = Some(run_at);
+        job.run_at = Some(schedule);
 
         job
     }
 
+    /// return the next time the job should run after `after`, skipping any dates excluded by the
+    /// calendar; None if the job has no schedule or will not run again.
+    pub fn next_fire(
+        &self,
+        after: &DateTime<Utc>,
+        calendar: Option<&Calendar>,
+    ) -> Option<DateTime<Utc>> {
+        let schedule = self.run_at.as_ref()?;
+        let mut after = *after;
+
+        loop {
+            let next = schedule.next_fire(&after)?;
+            let last_excluded = match calendar {
+                Some(calendar) => calendar.excluded_through(&schedule.local_date(&next)?),
+                None => None,
+            };
+
+            match last_excluded {
+                // jump past the excluded dates rather than testing each run
+                Some(last) => {
+                    after = schedule.start_of_day(&last.succ_opt()?)? - Duration::nanoseconds(1);
+                }
+                None => return Some(next),
+            }
+        }
+    }
+
+    /// return the scheduled times after `last_fired` up to `now` that the misfire policy says to
+    /// catch up, oldest first: none, the most recent one, or the most recent n.
+    pub fn missed_fires(
+        &self,
+        last_fired: &DateTime<Utc>,
+        now: &DateTime<Utc>,
+        calendar: Option<&Calendar>,
+    ) -> Vec<DateTime<Utc>> {
+        let limit = match self.misfire {
+            MisfirePolicy::Ignore => 0,
+            MisfirePolicy::RunOnce => 1,
+            MisfirePolicy::RunAll(max) => max as usize,
+        };
+
+        let mut missed = VecDeque::new();
+        if limit == 0 {
+            return missed.into();
+        }
+
+        let mut after = *last_fired;
+        let mut count = 0;
+        while let Some(at) = self.next_fire(&after, calendar).filter(|at| at <= now) {
+            if missed.len() == limit {
+                missed.pop_front();
+            }
+            missed.push_back(at);
+            after = at;
+
+            count += 1;
+            if count == MAX_MISSED_SCAN {
+                warn!("stopped looking for missed runs after {} runs", count);
+                break;
+            }
+        }
+
+        missed.into()
+    }
+
     /// create a new job wrapper model
     pub fn create_model(job: &Job) -> Model<Job> {
         let hash = Model::calc_hash(job);
@@ -77,9 +244,17 @@ impl Job {
 
         model
     }
+
+    /// return a copy of the model with the new status, versioned with the hash of its current value
+    pub fn update_model(model: &Model<Job>, status: Status) -> Model<Job> {
+        let version = Version::new(Model::calc_hash(&model.value));
+
+        Model::create_model(model.key.to_string(), &version, &status, &model.value)
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -105,4 +280,96 @@ mod tests {
         let jmodel: Model<Job> = serde_json::from_str(json.as_str()).unwrap();
         assert_eq!(model, jmodel);
     }
+
+    #[test]
+    fn next_fire_with_calendar() {
+        let mut run_at = RunAt::from_cron("0 9 * * *").unwrap();
+        run_at.timezone = Some("America/New_York".to_string());
+        let mut job = Job::with_run_at("report", "report.sh", run_at);
+        job.calendar = Some("us-holidays".to_string());
+
+        let date = |value: &str| chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap();
+        let utc = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        let mut calendar = Calendar::new("us-holidays");
+        calendar.exclude_date(date("2022-12-26"), "christmas observed");
+        calendar.exclude_range(date("2022-12-30"), date("2023-01-02"), "new year");
+
+        let after = utc("2022-12-25T15:00:00Z");
+        assert_eq!(
+            job.next_fire(&after, None),
+            Some(utc("2022-12-26T14:00:00Z"))
+        );
+        assert_eq!(
+            job.next_fire(&after, Some(&calendar)),
+            Some(utc("2022-12-27T14:00:00Z"))
+        );
+
+        let after = utc("2022-12-29T15:00:00Z");
+        assert_eq!(
+            job.next_fire(&after, Some(&calendar)),
+            Some(utc("2023-01-03T14:00:00Z"))
+        );
+
+        let job = Job::new("no schedule", "backup");
+        assert_eq!(job.next_fire(&after, Some(&calendar)), None);
+    }
+
+    #[test]
+    fn missed_fires() {
+        let utc = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let mut run_at = RunAt::from_cron("0 * * * *").unwrap();
+        run_at.timezone = Some("UTC".to_string());
+        let mut job = Job::with_run_at("hourly", "report.sh", run_at);
+
+        let last = utc("2022-12-25T08:00:00Z");
+        let now = utc("2022-12-25T12:30:00Z");
+        assert!(job.missed_fires(&last, &now, None).is_empty());
+
+        job.misfire = MisfirePolicy::RunOnce;
+        assert_eq!(
+            job.missed_fires(&last, &now, None),
+            vec![utc("2022-12-25T12:00:00Z")]
+        );
+
+        job.misfire = MisfirePolicy::RunAll(3);
+        assert_eq!(
+            job.missed_fires(&last, &now, None),
+            vec![
+                utc("2022-12-25T10:00:00Z"),
+                utc("2022-12-25T11:00:00Z"),
+                utc("2022-12-25T12:00:00Z"),
+            ]
+        );
+
+        // nothing was missed
+        assert!(job.missed_fires(&now, &now, None).is_empty());
+
+        let json = r#"{"topic":"t","description":"","run_at":null,"action":"a","pid":null,"results":null,"log":[],"errors":[],"misfire":{"run_all":10}}"#;
+        let job: Job = serde_json::from_str(json).unwrap();
+        assert_eq!(job.misfire, MisfirePolicy::RunAll(10));
+    }
+
+    #[test]
+    fn with_schedule() {
+        let job = Job::with_run_at("cron", "backup", RunAt::with_minutes(&vec![5u8]));
+        let json = serde_json::to_string(&job).unwrap();
+        let jjob: Job = serde_json::from_str(json.as_str()).unwrap();
+        assert_eq!(jjob, job);
+        assert!(matches!(jjob.run_at, Some(Schedule::Cron(_))));
+
+        let job = Job::with_schedule("cleanup", "rm -f /tmp/old", Schedule::after_delay(300));
+        let json = serde_json::to_string(&job).unwrap();
+        let jjob: Job = serde_json::from_str(json.as_str()).unwrap();
+        assert_eq!(jjob, job);
+        assert!(matches!(jjob.run_at, Some(Schedule::Delay { .. })));
+    }
 }
@@ -0,0 +1,132 @@
+/// ResourceLimits - the resources a job's process may use and the user it runs as: rlimits on cpu
+/// time, address space, open files and processes, an optional cgroup v2 placement with memory and
+/// cpu quotas, and an optional uid/gid.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// the cgroup v2 mount point; job cgroups are created below it
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    #[serde(default)]
+    pub cpu_seconds: Option<u64>, // RLIMIT_CPU, the process gets SIGXCPU when it runs out
+    #[serde(default)]
+    pub address_space_bytes: Option<u64>, // RLIMIT_AS, allocations beyond it fail
+    #[serde(default)]
+    pub open_files: Option<u64>, // RLIMIT_NOFILE
+    #[serde(default)]
+    pub processes: Option<u64>, // RLIMIT_NPROC, counted per user so best used with `user`
+    #[serde(default)]
+    pub cgroup: Option<String>, // a cgroup path relative to the cgroup root, e.g. jobs/backup
+    #[serde(default)]
+    pub memory_max_bytes: Option<u64>, // cgroup memory.max, the job is OOM killed above it
+    #[serde(default)]
+    pub cpu_max_percent: Option<u32>, // cgroup cpu.max as a percent of one cpu
+    #[serde(default)]
+    pub user: Option<u32>, // uid to run as
+    #[serde(default)]
+    pub group: Option<u32>, // gid to run as
+}
+
+impl ResourceLimits {
+    /// return true if no limit is set
+    pub fn is_empty(&self) -> bool {
+        *self == ResourceLimits::default()
+    }
+
+    /// return the rlimits to set as (name, value) pairs
+    pub fn rlimits(&self) -> Vec<(&'static str, u64)> {
+        [
+            ("cpu_seconds", self.cpu_seconds),
+            ("address_space_bytes", self.address_space_bytes),
+            ("open_files", self.open_files),
+            ("processes", self.processes),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.map(|value| (*name, value)))
+        .collect()
+    }
+
+    /// return the full path of the job's cgroup; an error if it leaves the cgroup root
+    pub fn cgroup_path(&self) -> Result<Option<PathBuf>> {
+        let cgroup = match &self.cgroup {
+            Some(cgroup) => cgroup,
+            None => return Ok(None),
+        };
+
+        let relative = Path::new(cgroup.trim_start_matches('/'));
+        let valid = relative.components().next().is_some()
+            && relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !valid {
+            return Err(anyhow!("invalid cgroup path: {}", cgroup));
+        }
+
+        Ok(Some(Path::new(CGROUP_ROOT).join(relative)))
+    }
+}
+
+impl fmt::Display for ResourceLimits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = self
+            .rlimits()
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+
+        if let Some(cgroup) = &self.cgroup {
+            parts.push(format!("cgroup={}", cgroup));
+        }
+        if let Some(bytes) = self.memory_max_bytes {
+            parts.push(format!("memory_max_bytes={}", bytes));
+        }
+        if let Some(percent) = self.cpu_max_percent {
+            parts.push(format!("cpu_max_percent={}", percent));
+        }
+        if let Some(user) = self.user {
+            parts.push(format!("user={}", user));
+        }
+        if let Some(group) = self.group {
+            parts.push(format!("group={}", group));
+        }
+
+        write!(f, "limits: {}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let limits: ResourceLimits =
+            serde_json::from_str(r#"{"cpu_seconds":10,"open_files":256,"cgroup":"jobs/backup"}"#)
+                .unwrap();
+        assert!(!limits.is_empty());
+        assert!(ResourceLimits::default().is_empty());
+        assert_eq!(
+            limits.rlimits(),
+            vec![("cpu_seconds", 10), ("open_files", 256)]
+        );
+        assert_eq!(
+            limits.to_string(),
+            "limits: cpu_seconds=10, open_files=256, cgroup=jobs/backup"
+        );
+        assert_eq!(
+            limits.cgroup_path().unwrap(),
+            Some(PathBuf::from("/sys/fs/cgroup/jobs/backup"))
+        );
+
+        let mut limits = ResourceLimits::default();
+        assert_eq!(limits.cgroup_path().unwrap(), None);
+        for cgroup in ["../etc", "jobs/../../etc", "", "/"] {
+            limits.cgroup = Some(cgroup.to_string());
+            assert!(limits.cgroup_path().is_err(), "{}", cgroup);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+/// Progress - a progress report from a running job: the step, 0..255, shown as `Active(step)`, and
+/// an optional message.  a job reports progress with a stdout line like `::progress::42 copying`,
+/// or by appending `42 copying` lines to the file named by the `JOB_PROGRESS_FILE` variable.
+use anyhow::{anyhow, Result};
+
+/// the prefix of a stdout progress line
+pub const PROGRESS_MARKER: &str = "::progress::";
+/// the environment variable that holds the path of the run's progress file
+pub const PROGRESS_FILE_VAR: &str = "JOB_PROGRESS_FILE";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub step: u8,
+    pub message: Option<String>,
+}
+
+impl Progress {
+    /// parse a report, the step then an optional message, e.g. "42 copying files"
+    pub fn parse(text: &str) -> Result<Progress> {
+        let text = text.trim();
+        let (step, message) = match text.split_once(char::is_whitespace) {
+            Some((step, message)) => (step, Some(message.trim().to_string())),
+            None => (text, None),
+        };
+
+        let step = step
+            .parse()
+            .map_err(|_| anyhow!("invalid progress step, 0..255: {}", text))?;
+
+        Ok(Progress { step, message })
+    }
+
+    /// return the report of a marked progress line, None if the line has no marker
+    pub fn marked(line: &str) -> Option<&str> {
+        line.strip_prefix(PROGRESS_MARKER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let progress = Progress::parse(Progress::marked("::progress::42 copying files").unwrap());
+        assert_eq!(
+            progress.unwrap(),
+            Progress {
+                step: 42,
+                message: Some("copying files".to_string())
+            }
+        );
+        assert_eq!(
+            Progress::parse("7\n").unwrap(),
+            Progress {
+                step: 7,
+                message: None
+            }
+        );
+
+        assert_eq!(Progress::marked("42 copying files"), None);
+        assert!(Progress::parse("300 too far").is_err());
+        assert!(Progress::parse("half way").is_err());
+        assert!(Progress::parse("").is_err());
+    }
+}
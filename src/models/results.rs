@@ -0,0 +1,83 @@
+/// Results - the structured results document of a run, any json value, e.g.
+/// `{"bytes_backed_up": 1024}`.  a job reports results by writing json to its `results_file` or by
+/// printing a final stdout line that starts with the `::results::` marker.
+///
+/// hashed and ordered by the json text so a Job can still be hashed for its model version.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+/// the prefix of the stdout line that holds the results json
+pub const RESULTS_MARKER: &str = "::results::";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Results(pub Value);
+
+impl Results {
+    /// parse the results json
+    pub fn parse(text: &str) -> Result<Results> {
+        serde_json::from_str(text.trim())
+            .map(Results)
+            .map_err(|e| anyhow!("could not parse results: {}", e))
+    }
+
+    /// return the json of a marked results line, None if the line has no marker
+    pub fn marked(line: &str) -> Option<&str> {
+        line.strip_prefix(RESULTS_MARKER)
+    }
+}
+
+impl Deref for Results {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl Hash for Results {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_string().hash(state);
+    }
+}
+
+impl PartialOrd for Results {
+    fn partial_cmp(&self, other: &Results) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Results {
+    fn cmp(&self, other: &Results) -> Ordering {
+        self.0.to_string().cmp(&other.0.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let line = r#"::results::{"bytes_backed_up": 1024, "files": ["a", "b"]}"#;
+        let results = Results::parse(Results::marked(line).unwrap()).unwrap();
+        assert_eq!(results["bytes_backed_up"], 1024);
+        assert_eq!(results["files"][1], "b");
+        assert_eq!(Results::marked("bytes: 1024"), None);
+
+        let error = Results::parse("{bytes").unwrap_err();
+        assert!(error.to_string().starts_with("could not parse results:"));
+
+        // the original plain string results are still accepted
+        let results: Results = serde_json::from_str(r#""done""#).unwrap();
+        assert_eq!(results, Results(Value::from("done")));
+        assert_eq!(
+            serde_json::to_string(&results).unwrap(),
+            r#""done""#.to_string()
+        );
+    }
+}
@@ -0,0 +1,133 @@
+/// RetryPolicy - how a failed run is retried: the number of attempts, an exponential backoff
+/// between them with optional jitter, and which exit codes are worth retrying.
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub max_attempts: u32, // total runs, including the first
+    #[serde(default = "RetryPolicy::default_initial_delay")]
+    pub initial_delay_seconds: u64,
+    #[serde(default = "RetryPolicy::default_multiplier")]
+    pub multiplier: u32,
+    #[serde(default = "RetryPolicy::default_max_delay")]
+    pub max_delay_seconds: u64,
+    #[serde(default)]
+    pub jitter_percent: u8, // shorten each delay by a random 0..jitter_percent %
+    #[serde(default)]
+    pub retry_exit_codes: Vec<u8>, // empty retries every failure, incl. timeouts and signals
+}
+
+impl RetryPolicy {
+    /// create a policy with the default backoff: 10 seconds, doubling, up to an hour
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay_seconds: RetryPolicy::default_initial_delay(),
+            multiplier: RetryPolicy::default_multiplier(),
+            max_delay_seconds: RetryPolicy::default_max_delay(),
+            jitter_percent: 0,
+            retry_exit_codes: Vec::new(),
+        }
+    }
+
+    fn default_initial_delay() -> u64 {
+        10
+    }
+
+    fn default_multiplier() -> u32 {
+        2
+    }
+
+    fn default_max_delay() -> u64 {
+        3600
+    }
+
+    /// return true if another attempt should follow the failed `attempt` (1 based); exit_code is
+    /// None when the process didn't exit on its own (timeout, signal, could not start)
+    pub fn should_retry(&self, attempt: u32, exit_code: Option<u8>) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+
+        if self.retry_exit_codes.is_empty() {
+            return true;
+        }
+
+        exit_code.map_or(false, |code| self.retry_exit_codes.contains(&code))
+    }
+
+    /// return the delay before the retry that follows the failed `attempt` (1 based), without jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = u64::from(self.multiplier.max(1))
+            .checked_pow(attempt.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        let seconds = self
+            .initial_delay_seconds
+            .saturating_mul(factor)
+            .min(self.max_delay_seconds);
+
+        Duration::from_secs(seconds)
+    }
+
+    /// return the backoff with the random jitter applied
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt);
+        let percent = u64::from(self.jitter_percent.min(100));
+        if percent == 0 {
+            return backoff;
+        }
+
+        // the std hasher is randomly keyed, enough randomness to spread retries apart
+        let random = RandomState::new().build_hasher().finish() % (percent + 1);
+        let millis = backoff.as_millis() as u64;
+
+        Duration::from_millis(millis - millis * random / 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let mut policy = RetryPolicy::new(10);
+        policy.initial_delay_seconds = 5;
+        policy.max_delay_seconds = 60;
+
+        let delays: Vec<u64> = (1..=6).map(|n| policy.backoff(n).as_secs()).collect();
+        assert_eq!(delays, vec![5, 10, 20, 40, 60, 60]);
+
+        // no overflow on large attempts
+        assert_eq!(policy.backoff(200).as_secs(), 60);
+
+        policy.jitter_percent = 50;
+        for _ in 0..20 {
+            let delay = policy.delay(3);
+            assert!(delay <= Duration::from_secs(20));
+            assert!(delay >= Duration::from_secs(10));
+        }
+    }
+
+    #[test]
+    fn should_retry() {
+        let mut policy = RetryPolicy::new(3);
+        assert!(policy.should_retry(1, Some(1)));
+        assert!(policy.should_retry(2, None));
+        assert!(!policy.should_retry(3, Some(1)));
+
+        policy.retry_exit_codes = vec![75];
+        assert!(policy.should_retry(1, Some(75)));
+        assert!(!policy.should_retry(1, Some(1)));
+        assert!(!policy.should_retry(1, None));
+    }
+
+    #[test]
+    fn serialize() {
+        let policy: RetryPolicy = serde_json::from_str(r#"{"max_attempts":4}"#).unwrap();
+        assert_eq!(policy, RetryPolicy::new(4));
+    }
+}
@@ -1,14 +1,203 @@
 /// RunAt - a cron like structure to specify when this job should run.
 ///
 /// follows cron attributes: see https://www.ibm.com/docs/en/db2oc?topic=task-unix-cron-format for definitions
-use chrono::{Datelike, NaiveDateTime, Timelike};
+use crate::models::cron;
+use anyhow::{anyhow, Result};
+use chrono::{
+    DateTime, Datelike, Duration, Local, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Timelike,
+    Utc,
+};
+use chrono_tz::Tz;
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 // use chrono::Weekday;
 
+const WEEKDAY_NAMES: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+const MONTH_NAMES: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+const MIN_YEAR: u16 = 1970;
+const MAX_YEAR: u16 = 2999;
+
+/// RunAtProblem - a single reason why a RunAt is not valid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum RunAtProblem {
+    /// a field value outside of its allowed range
+    OutOfRange {
+        field: &'static str,
+        value: u16,
+        min: u16,
+        max: u16,
+    },
+    /// every listed year is before the current year
+    YearsInPast(Vec<u16>),
+    /// the time zone name is not a known IANA zone
+    UnknownTimeZone(String),
+    /// the fields combine in a way that can never match
+    NeverMatches(String),
+}
+
+impl fmt::Display for RunAtProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunAtProblem::OutOfRange {
+                field,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} value {} is out of range {}..{}",
+                field, value, min, max
+            ),
+            RunAtProblem::YearsInPast(years) => write!(f, "years {:?} are all in the past", years),
+            RunAtProblem::UnknownTimeZone(name) => write!(f, "unknown time zone '{}'", name),
+            RunAtProblem::NeverMatches(reason) => write!(f, "schedule never matches: {}", reason),
+        }
+    }
+}
+
+/// RunAtError - the list of problems found when validating a RunAt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RunAtError {
+    pub problems: Vec<RunAtProblem>,
+}
+
+impl fmt::Display for RunAtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list: Vec<String> = self.problems.iter().map(|p| p.to_string()).collect();
+        write!(f, "invalid run at: {}", list.join("; "))
+    }
+}
+
+impl std::error::Error for RunAtError {}
+
+/// DayRule - quartz style day rules (L, W and #) that the plain day lists can't express.
+///
+/// day of month rules are or'ed with `days_of_month`, day of week rules with `days_of_week`.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DayRule {
+    /// L or L-n: the last day of the month, less the offset in days
+    LastDayOfMonth(u8),
+    /// LW: the last weekday (monday..friday) of the month
+    LastWeekdayOfMonth,
+    /// nW: the weekday nearest to day n without crossing into another month
+    NearestWeekday(u8),
+    /// nL: the last day n of the week in the month, e.g. 5L is the last friday
+    LastDayOfWeek(u8),
+    /// d#n: the nth day d of the week in the month, e.g. 2#2 is the 2nd tuesday
+    NthDayOfWeek { day: u8, nth: u8 },
+}
+
+impl DayRule {
+    /// return true if this rule is part of the day of month field, else the day of week field
+    pub fn is_day_of_month(&self) -> bool {
+        matches!(
+            self,
+            DayRule::LastDayOfMonth(_) | DayRule::LastWeekdayOfMonth | DayRule::NearestWeekday(_)
+        )
+    }
+
+    /// return an english description of the rule, e.g. "the 2nd tuesday of the month"
+    pub fn describe(&self) -> String {
+        match self {
+            DayRule::LastDayOfMonth(0) => "the last day of the month".to_string(),
+            DayRule::LastDayOfMonth(offset) => {
+                format!("{} days before the last day of the month", offset)
+            }
+            DayRule::LastWeekdayOfMonth => "the last weekday of the month".to_string(),
+            DayRule::NearestWeekday(day) => format!("the weekday nearest day {}", day),
+            DayRule::LastDayOfWeek(day) => format!(
+                "the last {} of the month",
+                WEEKDAY_NAMES[(*day % 7) as usize]
+            ),
+            DayRule::NthDayOfWeek { day, nth } => {
+                let suffix = match nth {
+                    1 => "st",
+                    2 => "nd",
+                    3 => "rd",
+                    _ => "th",
+                };
+                format!(
+                    "the {}{} {} of the month",
+                    nth,
+                    suffix,
+                    WEEKDAY_NAMES[(*day % 7) as usize]
+                )
+            }
+        }
+    }
+
+    /// return true if the date satisfies the rule
+    pub fn matches(&self, date: &NaiveDate) -> bool {
+        let day = date.day() as u8;
+        let day_of_week = date.weekday().num_days_from_sunday() as u8;
+        let last = match last_day_of_month(date) {
+            Some(last) => last,
+            None => return false,
+        };
+
+        match self {
+            DayRule::LastDayOfMonth(offset) => last > *offset && day == last - offset,
+            DayRule::LastWeekdayOfMonth => {
+                let last_weekday = match weekday_of(date, last) {
+                    6 => last - 1,
+                    0 => last - 2,
+                    _ => last,
+                };
+                day == last_weekday
+            }
+            DayRule::NearestWeekday(target) => {
+                if *target > last || *target == 0 {
+                    return false;
+                }
+
+                let nearest = match weekday_of(date, *target) {
+                    6 if *target == 1 => 3, // saturday the 1st moves forward to monday the 3rd
+                    6 => target - 1,
+                    0 if *target == last => target - 2,
+                    0 => target + 1,
+                    _ => *target,
+                };
+                day == nearest
+            }
+            DayRule::LastDayOfWeek(dow) => day_of_week == dow % 7 && day + 7 > last,
+            DayRule::NthDayOfWeek { day: dow, nth } => {
+                day_of_week == dow % 7 && (day - 1) / 7 + 1 == *nth
+            }
+        }
+    }
+}
+
 /// RunAt - a cron like structure to specify when this job should run.
 #[derive(Debug, Default, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct RunAt {
+    /// range of 0..59 inclusive; None (the default) means second 0, an empty vec any second
+    #[serde(default)]
+    pub seconds: Option<Vec<u8>>, // 0..59
     /// range of 0..59 inclusive
     pub minutes: Vec<u8>, // 0..59
     /// range of 0..23 inclusive
@@ -21,18 +210,27 @@ pub struct RunAt {
     pub months: Vec<u8>, // 1..12
     /// range of current to 2999
     pub years: Vec<u16>, // current..2999
+    /// optional IANA time zone name, e.g. "America/Los_Angeles"; the server's local time if None
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// quartz style rules: last day, nearest weekday, last or nth day of the week
+    #[serde(default)]
+    pub day_rules: Vec<DayRule>,
 }
 
 impl RunAt {
     /// create a new RunAt struct with zero length vec values
     pub fn new() -> RunAt {
         RunAt {
+            seconds: None,
             minutes: Vec::new(),
             hours: Vec::new(),
             days_of_month: Vec::new(),
             days_of_week: Vec::new(),
             months: Vec::new(),
             years: Vec::new(),
+            timezone: None,
+            day_rules: Vec::new(),
         }
     }
 
@@ -54,9 +252,215 @@ impl RunAt {
         at
     }
 
-    /// returns true if the RunAt struct is valid.  checks all vec values for the proper range.
-    pub fn is_valid(&self) -> bool {
-        true
+    /// create a new RunAt struct from a five field cron expression, e.g. "*/15 9-17 * * 1-5", or
+    /// a six field expression with leading seconds, e.g. "*/10 * * * * *".
+    /// errors name the field and value that could not be parsed.
+    pub fn from_cron(expr: &str) -> Result<RunAt> {
+        cron::parse(expr)
+    }
+
+    /// return the schedule as a cron expression that parses back to the same RunAt
+    pub fn to_cron_string(&self) -> String {
+        cron::format(self)
+    }
+
+    /// return an english description of the schedule, e.g.
+    /// "at minutes 10, 20 and 30 past hours 0, 1, 10 and 20, every day"
+    pub fn describe(&self) -> String {
+        let trivial_seconds = match &self.seconds {
+            None => true,
+            Some(list) => list.as_slice() == [0u8],
+        };
+
+        let mut parts: Vec<String> = Vec::new();
+
+        let time = if trivial_seconds && self.minutes.len() == 1 && self.hours.len() == 1 {
+            format!("at {:02}:{:02}", self.hours[0], self.minutes[0])
+        } else {
+            let seconds = match &self.seconds {
+                _ if trivial_seconds => String::new(),
+                Some(list) if list.is_empty() => "every second of ".to_string(),
+                Some(list) => format!("at {} ", plural("second", list)),
+                None => String::new(),
+            };
+
+            let minutes = if self.minutes.is_empty() {
+                "every minute".to_string()
+            } else {
+                plural("minute", &self.minutes)
+            };
+
+            let hours = if self.hours.is_empty() {
+                if self.minutes.is_empty() {
+                    String::new()
+                } else {
+                    " past every hour".to_string()
+                }
+            } else if self.minutes.is_empty() {
+                format!(" of {}", plural("hour", &self.hours))
+            } else {
+                format!(" past {}", plural("hour", &self.hours))
+            };
+
+            let at = if seconds.is_empty() && !self.minutes.is_empty() {
+                "at "
+            } else {
+                ""
+            };
+
+            format!("{}{}{}{}", at, seconds, minutes, hours)
+        };
+        parts.push(time);
+
+        let mut days: Vec<String> = Vec::new();
+        for rule in self.day_rules.iter().filter(|rule| rule.is_day_of_month()) {
+            days.push(rule.describe());
+        }
+        if !self.days_of_month.is_empty() {
+            days.insert(
+                0,
+                format!("{} of the month", plural("day", &self.days_of_month)),
+            );
+        }
+
+        let mut weekdays: Vec<String> = self
+            .days_of_week
+            .iter()
+            .map(|day| WEEKDAY_NAMES[(*day % 7) as usize].to_string())
+            .collect();
+        weekdays.dedup();
+        for rule in self.day_rules.iter().filter(|rule| !rule.is_day_of_month()) {
+            weekdays.push(rule.describe());
+        }
+
+        parts.push(match (days.is_empty(), weekdays.is_empty()) {
+            (true, true) => "every day".to_string(),
+            (false, true) => format!("on {}", join_and(&days, "or")),
+            (true, false) => format!("on {}", join_and(&weekdays, "or")),
+            // crontab runs on either
+            (false, false) => {
+                days.extend(weekdays);
+                format!("on {}", join_and(&days, "or"))
+            }
+        });
+
+        if !self.months.is_empty() {
+            let months: Vec<String> = self
+                .months
+                .iter()
+                .filter_map(|m| MONTH_NAMES.get((*m as usize).wrapping_sub(1)))
+                .map(|m| m.to_string())
+                .collect();
+            parts.push(format!("in {}", join_and(&months, "and")));
+        }
+
+        if !self.years.is_empty() {
+            let years: Vec<String> = self.years.iter().map(|y| y.to_string()).collect();
+            parts.push(format!("in {}", join_and(&years, "and")));
+        }
+
+        let text = parts.join(", ");
+        match &self.timezone {
+            Some(zone) => format!("{} ({})", text, zone),
+            None => text,
+        }
+    }
+
+    /// returns Ok if the RunAt struct is valid.  checks all vec values for the proper range and
+    /// rejects combinations that can never match, e.g. february 30th or years all in the past.
+    /// the error lists every problem found.
+    pub fn is_valid(&self) -> std::result::Result<(), RunAtError> {
+        let mut problems: Vec<RunAtProblem> = Vec::new();
+
+        let mut check = |field: &'static str, values: Vec<u16>, min: u16, max: u16| {
+            for value in values {
+                if value < min || value > max {
+                    problems.push(RunAtProblem::OutOfRange {
+                        field,
+                        value,
+                        min,
+                        max,
+                    });
+                }
+            }
+        };
+
+        let widen = |list: &[u8]| list.iter().map(|v| *v as u16).collect::<Vec<u16>>();
+        if let Some(seconds) = &self.seconds {
+            check("seconds", widen(seconds), 0, 59);
+        }
+        check("minutes", widen(&self.minutes), 0, 59);
+        check("hours", widen(&self.hours), 0, 23);
+        check("days_of_month", widen(&self.days_of_month), 1, 31);
+        check("days_of_week", widen(&self.days_of_week), 0, 7);
+        check("months", widen(&self.months), 1, 12);
+        check("years", self.years.clone(), MIN_YEAR, MAX_YEAR);
+
+        for rule in &self.day_rules {
+            match rule {
+                DayRule::LastDayOfMonth(offset) => check("day_rules", vec![*offset as u16], 0, 30),
+                DayRule::LastWeekdayOfMonth => (),
+                DayRule::NearestWeekday(day) => check("day_rules", vec![*day as u16], 1, 31),
+                DayRule::LastDayOfWeek(day) => check("day_rules", vec![*day as u16], 0, 7),
+                DayRule::NthDayOfWeek { day, nth } => {
+                    check("day_rules", vec![*day as u16], 0, 7);
+                    check("day_rules", vec![*nth as u16], 1, 5);
+                }
+            }
+        }
+
+        if let Some(name) = &self.timezone {
+            if self.time_zone().is_err() {
+                problems.push(RunAtProblem::UnknownTimeZone(name.to_string()));
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(RunAtError { problems });
+        }
+
+        let now = self
+            .local_datetime(&Utc::now())
+            .unwrap_or_else(|| Utc::now().naive_utc());
+        if !self.years.is_empty() && self.years.iter().all(|y| (*y as i32) < now.year()) {
+            problems.push(RunAtProblem::YearsInPast(self.years.clone()));
+        } else if !self.days_of_month.is_empty()
+            && self.days_of_week.is_empty()
+            && self.day_rules.is_empty()
+        {
+            let months = if self.months.is_empty() {
+                (1u8..=12u8).collect()
+            } else {
+                self.months.clone()
+            };
+
+            let possible = months.iter().any(|month| {
+                self.days_of_month
+                    .iter()
+                    .any(|day| *day <= days_in_month(*month))
+            });
+
+            if !possible {
+                problems.push(RunAtProblem::NeverMatches(format!(
+                    "days {:?} do not exist in months {:?}",
+                    self.days_of_month, months
+                )));
+            }
+        }
+
+        // catch anything else, e.g. february 29th restricted to a non-leap year
+        if problems.is_empty() && self.next_after(&now).is_none() {
+            problems.push(RunAtProblem::NeverMatches(format!(
+                "no matching time after {}",
+                now.format("%Y-%m-%d %H:%M")
+            )));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(RunAtError { problems })
+        }
     }
 
     /// return true if minute value is in the range of 0..59 inclusive, else false.
@@ -64,58 +468,520 @@ impl RunAt {
         self.validate_range(minute, 0u8, 59u8)
     }
 
+    /// return true if second value is in the range of 0..59 inclusive, else false.
+    pub fn valid_second(&self, second: u8) -> bool {
+        self.validate_range(second, 0u8, 59u8)
+    }
+
     /// return true if hour value in the range of 0..23 inclusive, else false.
     pub fn valid_hour(&self, hour: u8) -> bool {
         self.validate_range(hour, 0u8, 23u8)
     }
 
+    /// return true if day of month value is in the range of 1..31 inclusive, else false.
+    pub fn valid_day_of_month(&self, day: u8) -> bool {
+        self.validate_range(day, 1u8, 31u8)
+    }
+
+    /// return true if day of week value is in the range of 0..7 inclusive, else false.
+    pub fn valid_day_of_week(&self, day: u8) -> bool {
+        self.validate_range(day, 0u8, 7u8)
+    }
+
+    /// return true if month value is in the range of 1..12 inclusive, else false.
+    pub fn valid_month(&self, month: u8) -> bool {
+        self.validate_range(month, 1u8, 12u8)
+    }
+
+    /// return true if year value is in the range of 1970..2999 inclusive, else false.
+    pub fn valid_year(&self, year: u16) -> bool {
+        (MIN_YEAR..=MAX_YEAR).contains(&year)
+    }
+
     fn validate_range(&self, value: u8, min: u8, max: u8) -> bool {
         value >= min && value <= max
     }
 
     /// return true if the supplied date is
     pub fn match_datetime(&self, dt: &NaiveDateTime) -> bool {
-        // return true if the vector is empty or the value is in the list
-        fn match_list(value: u8, list: &Vec<u8>) -> bool {
-            list.is_empty() || list.contains(&value)
+        let second = dt.time().second() as u8;
+        let minute = dt.time().minute() as u8;
+        let hour = dt.time().hour() as u8;
+
+        self.match_date(&dt.date())
+            && match_list(hour, &self.hours)
+            && match_list(minute, &self.minutes)
+            && match_list(second, self.second_list())
+    }
+
+    /// the seconds to match; minute level schedules (seconds is None) fire on second 0
+    fn second_list(&self) -> &[u8] {
+        match &self.seconds {
+            Some(list) => list,
+            None => &[0u8],
         }
+    }
+
+    /// return true if the year and month of the date match and its day does.  as in crontab, when
+    /// both the day of month and the day of week are restricted a day that matches either one is
+    /// enough, e.g. "0 0 13 * 5" runs on the 13th and on every friday.
+    fn match_date(&self, date: &NaiveDate) -> bool {
+        let day = match (self.match_day_of_month(date), self.match_day_of_week(date)) {
+            (Some(by_month), Some(by_week)) => by_month || by_week,
+            (by_month, by_week) => by_month.unwrap_or(true) && by_week.unwrap_or(true),
+        };
 
-        fn match_years(value: u16, list: &Vec<u16>) -> bool {
-            list.is_empty() || list.contains(&value)
+        day && match_years(date.year() as u16, &self.years)
+            && match_list(date.month() as u8, &self.months)
+    }
+
+    // true if either the day list or a day of month rule matches; None if neither is set
+    fn match_day_of_month(&self, date: &NaiveDate) -> Option<bool> {
+        let mut rules = self.day_rules.iter().filter(|rule| rule.is_day_of_month());
+        let day = date.day() as u8;
+
+        match (
+            self.days_of_month.is_empty(),
+            rules.clone().next().is_none(),
+        ) {
+            (true, true) => None,
+            _ => Some(self.days_of_month.contains(&day) || rules.any(|rule| rule.matches(date))),
         }
+    }
 
-        let minute = dt.time().minute() as u8;
-        let hour = dt.time().hour() as u8;
-        let day_of_week = (dt.date().weekday().num_days_from_sunday() % 7) as u8;
-        let day_of_month = dt.date().day() as u8;
-        let month = dt.month() as u8;
-        let year = dt.year() as u16;
+    // true if either the day list or a day of week rule matches; None if neither is set
+    fn match_day_of_week(&self, date: &NaiveDate) -> Option<bool> {
+        let mut rules = self.day_rules.iter().filter(|rule| !rule.is_day_of_month());
+        let day_of_week = date.weekday().num_days_from_sunday() as u8;
+
+        match (self.days_of_week.is_empty(), rules.clone().next().is_none()) {
+            (true, true) => None,
+            // sunday may be listed as 0 or 7
+            _ => Some(
+                self.days_of_week.contains(&day_of_week)
+                    || (day_of_week == 0 && self.days_of_week.contains(&7))
+                    || rules.any(|rule| rule.matches(date)),
+            ),
+        }
+    }
+
+    /// return the parsed time zone, None if not set, or an error if the name is unknown
+    pub fn time_zone(&self) -> Result<Option<Tz>> {
+        match &self.timezone {
+            Some(name) => match name.parse::<Tz>() {
+                Ok(tz) => Ok(Some(tz)),
+                Err(e) => Err(anyhow!("unknown time zone '{}': {}", name, e)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// convert the instant to local time in this schedule's zone; None if the zone is unknown
+    pub fn local_datetime(&self, instant: &DateTime<Utc>) -> Option<NaiveDateTime> {
+        match self.time_zone() {
+            Ok(Some(tz)) => Some(instant.with_timezone(&tz).naive_local()),
+            Ok(None) => Some(instant.with_timezone(&Local).naive_local()),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// convert a local time in this schedule's zone to an instant; a local time skipped by a
+    /// daylight saving transition becomes the transition.  None if the zone is unknown.
+    pub fn local_to_utc(&self, local: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        fn convert<Z: TimeZone>(zone: &Z, local: &NaiveDateTime) -> Option<DateTime<Utc>> {
+            match zone.from_local_datetime(local).earliest() {
+                Some(dt) => Some(dt.with_timezone(&Utc)),
+                None => transition_after(zone, local),
+            }
+        }
+
+        match self.time_zone() {
+            Ok(Some(tz)) => convert(&tz, local),
+            Ok(None) => convert(&Local, local),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// return true if the instant matches when viewed in this schedule's time zone
+    pub fn matches_at(&self, instant: &DateTime<Utc>) -> bool {
+        match self.local_datetime(instant) {
+            Some(local) => self.match_datetime(&local),
+            None => false,
+        }
+    }
+
+    /// return the first instant strictly after `after` that matches in this schedule's time zone.
+    ///
+    /// daylight saving rules: a local time skipped by a forward transition fires once at the
+    /// transition; a local time repeated by a backward transition fires only the first time.
+    pub fn next_fire(&self, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.time_zone() {
+            Ok(Some(tz)) => self.next_fire_in(&tz, after),
+            Ok(None) => self.next_fire_in(&Local, after),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }
+    }
+
+    fn next_fire_in<Z: TimeZone>(&self, zone: &Z, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut local = after.with_timezone(zone).naive_local();
+
+        loop {
+            let candidate = self.next_after(&local)?;
+            let instant = match zone.from_local_datetime(&candidate) {
+                LocalResult::Single(dt) => dt.with_timezone(&Utc),
+                LocalResult::Ambiguous(first, _) => first.with_timezone(&Utc),
+                LocalResult::None => transition_after(zone, &candidate)?,
+            };
+
+            if instant > *after {
+                return Some(instant);
+            }
+
+            local = candidate;
+        }
+    }
+
+    /// return the first date time strictly after `dt` that matches, or None if the schedule
+    /// can never fire again, e.g. all of the years are in the past.  Rather than testing each
+    /// second this jumps field by field: year, month, day, hour, minute then second.
+    pub fn next_after(&self, dt: &NaiveDateTime) -> Option<NaiveDateTime> {
+        // start at the next whole second
+        let mut next =
+            dt.date().and_hms_opt(dt.hour(), dt.minute(), dt.second())? + Duration::seconds(1);
+
+        // the calendar repeats every 400 years, so if nothing matches by then nothing ever will
+        let last_year = match self.years.iter().max() {
+            Some(year) => *year as i32,
+            None => next.year() + 400,
+        };
+
+        while next.year() <= last_year {
+            let date = next.date();
+
+            if !match_years(date.year() as u16, &self.years) {
+                let year = self
+                    .years
+                    .iter()
+                    .filter(|y| **y as i32 > date.year())
+                    .min()?;
+                next = NaiveDate::from_ymd_opt(*year as i32, 1, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+
+            if !match_list(date.month() as u8, &self.months) {
+                next = first_of_next_month(&date)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+
+            if !self.match_date(&date) {
+                next = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+
+            let hour = next.hour() as u8;
+            match next_in_list(hour, &self.hours, 23) {
+                Some(h) if h == hour => (),
+                Some(h) => {
+                    next = date.and_hms_opt(h as u32, 0, 0)?;
+                    continue;
+                }
+                None => {
+                    next = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+                    continue;
+                }
+            }
+
+            let minute = next.minute() as u8;
+            match next_in_list(minute, &self.minutes, 59) {
+                Some(m) if m == minute => (),
+                Some(m) => {
+                    next = date.and_hms_opt(hour as u32, m as u32, 0)?;
+                    continue;
+                }
+                None => {
+                    next = date.and_hms_opt(hour as u32, 0, 0)? + Duration::hours(1);
+                    continue;
+                }
+            }
+
+            match next_in_list(next.second() as u8, self.second_list(), 59) {
+                Some(sec) => return date.and_hms_opt(hour as u32, minute as u32, sec as u32),
+                None => {
+                    next = date.and_hms_opt(hour as u32, minute as u32, 0)? + Duration::minutes(1);
+                    continue;
+                }
+            }
+        }
+
+        None
+    }
+
+    /// return the last date time strictly before `dt` that matches, or None if there is none;
+    /// the mirror image of `next_after`.
+    pub fn prev_before(&self, dt: &NaiveDateTime) -> Option<NaiveDateTime> {
+        // start at the current whole second if dt is past it, else the previous second
+        let floor = dt.date().and_hms_opt(dt.hour(), dt.minute(), dt.second())?;
+        let mut prev = if floor < *dt {
+            floor
+        } else {
+            floor - Duration::seconds(1)
+        };
+
+        let first_year = match self.years.iter().min() {
+            Some(year) => *year as i32,
+            None => prev.year() - 400,
+        };
+
+        while prev.year() >= first_year {
+            let date = prev.date();
+
+            if !match_years(date.year() as u16, &self.years) {
+                let year = self
+                    .years
+                    .iter()
+                    .filter(|y| (**y as i32) < date.year())
+                    .max()?;
+                prev = NaiveDate::from_ymd_opt(*year as i32, 12, 31)?.and_hms_opt(23, 59, 59)?;
+                continue;
+            }
+
+            if !match_list(date.month() as u8, &self.months) {
+                prev = date.with_day(1)?.pred_opt()?.and_hms_opt(23, 59, 59)?;
+                continue;
+            }
+
+            if !self.match_date(&date) {
+                prev = date.pred_opt()?.and_hms_opt(23, 59, 59)?;
+                continue;
+            }
+
+            let hour = prev.hour() as u8;
+            match prev_in_list(hour, &self.hours) {
+                Some(h) if h == hour => (),
+                Some(h) => {
+                    prev = date.and_hms_opt(h as u32, 59, 59)?;
+                    continue;
+                }
+                None => {
+                    prev = date.pred_opt()?.and_hms_opt(23, 59, 59)?;
+                    continue;
+                }
+            }
+
+            let minute = prev.minute() as u8;
+            match prev_in_list(minute, &self.minutes) {
+                Some(m) if m == minute => (),
+                Some(m) => {
+                    prev = date.and_hms_opt(hour as u32, m as u32, 59)?;
+                    continue;
+                }
+                None => {
+                    prev = date.and_hms_opt(hour as u32, 0, 0)? - Duration::seconds(1);
+                    continue;
+                }
+            }
 
-        if !match_years(year, &self.years) {
-            return false;
+            match prev_in_list(prev.second() as u8, self.second_list()) {
+                Some(sec) => return date.and_hms_opt(hour as u32, minute as u32, sec as u32),
+                None => {
+                    prev = date.and_hms_opt(hour as u32, minute as u32, 0)? - Duration::seconds(1);
+                    continue;
+                }
+            }
         }
 
-        if !match_list(month, &self.months) {
-            return false;
+        None
+    }
+
+    /// return a lazy iterator of the matching date times at or after `start`, in order
+    pub fn occurrences(&self, start: &NaiveDateTime) -> Occurrences<'_> {
+        Occurrences {
+            run_at: self,
+            cursor: Some(*start - Duration::nanoseconds(1)),
         }
+    }
+
+    /// return a lazy iterator of the matching date times strictly before `end`, in reverse order
+    pub fn occurrences_before(&self, end: &NaiveDateTime) -> OccurrencesBefore<'_> {
+        OccurrencesBefore {
+            run_at: self,
+            cursor: Some(*end),
+        }
+    }
+}
+
+/// Occurrences - iterates forward through the matching date times of a RunAt.
+#[derive(Debug, Clone)]
+pub struct Occurrences<'a> {
+    run_at: &'a RunAt,
+    cursor: Option<NaiveDateTime>,
+}
+
+impl<'a> Iterator for Occurrences<'a> {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<NaiveDateTime> {
+        self.cursor = self.run_at.next_after(&self.cursor?);
+        self.cursor
+    }
+}
+
+/// OccurrencesBefore - iterates backward through the matching date times of a RunAt.
+#[derive(Debug, Clone)]
+pub struct OccurrencesBefore<'a> {
+    run_at: &'a RunAt,
+    cursor: Option<NaiveDateTime>,
+}
+
+impl<'a> Iterator for OccurrencesBefore<'a> {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<NaiveDateTime> {
+        self.cursor = self.run_at.prev_before(&self.cursor?);
+        self.cursor
+    }
+}
 
-        if !match_list(day_of_month, &self.days_of_month) {
-            return false;
+// return true if the vector is empty or the value is in the list
+fn match_list(value: u8, list: &[u8]) -> bool {
+    list.is_empty() || list.contains(&value)
+}
+
+fn match_years(value: u16, list: &[u16]) -> bool {
+    list.is_empty() || list.contains(&value)
+}
+
+// return the smallest value >= current that matches the list (empty matches any), up to max
+fn next_in_list(current: u8, list: &[u8], max: u8) -> Option<u8> {
+    if list.is_empty() {
+        return Some(current).filter(|v| *v <= max);
+    }
+
+    list.iter()
+        .filter(|v| **v >= current && **v <= max)
+        .min()
+        .copied()
+}
+
+// return the largest value <= current that matches the list (empty matches any)
+fn prev_in_list(current: u8, list: &[u8]) -> Option<u8> {
+    if list.is_empty() {
+        return Some(current);
+    }
+
+    list.iter().filter(|v| **v <= current).max().copied()
+}
+
+// return the instant a skipped local time jumps to, i.e. the first valid local minute after it
+fn transition_after<Z: TimeZone>(zone: &Z, skipped: &NaiveDateTime) -> Option<DateTime<Utc>> {
+    // transitions happen on a minute boundary
+    let mut local = skipped
+        .date()
+        .and_hms_opt(skipped.hour(), skipped.minute(), 0)?;
+    for _ in 0..(24 * 60) {
+        local += Duration::minutes(1);
+        if let Some(dt) = zone.from_local_datetime(&local).earliest() {
+            return Some(dt.with_timezone(&Utc));
         }
+    }
+
+    None
+}
+
+// "minute 5" or "minutes 0, 15, 30 and 45"; values are sorted, runs of 3 or more become ranges
+fn plural(name: &str, values: &[u8]) -> String {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
 
-        if !match_list(day_of_week, &self.days_of_week) {
-            return false;
+    let mut items: Vec<String> = Vec::new();
+    let mut idx = 0;
+    while idx < sorted.len() {
+        let mut end = idx;
+        while end + 1 < sorted.len() && sorted[end + 1] == sorted[end] + 1 {
+            end += 1;
         }
 
-        if match_list(hour, &self.hours) && match_list(minute, &self.minutes) {
-            return true;
+        if end - idx >= 2 {
+            items.push(format!("{} through {}", sorted[idx], sorted[end]));
+        } else {
+            items.extend(sorted[idx..=end].iter().map(|v| v.to_string()));
         }
 
-        false
+        idx = end + 1;
+    }
+
+    if sorted.len() == 1 {
+        format!("{} {}", name, items[0])
+    } else {
+        format!("{}s {}", name, join_and(&items, "and"))
+    }
+}
+
+// "a", "a and b", "a, b and c"
+fn join_and(items: &[String], conjunction: &str) -> String {
+    match items {
+        [] => String::new(),
+        [one] => one.to_string(),
+        [head @ .., last] => format!("{} {} {}", head.join(", "), conjunction, last),
+    }
+}
+
+// return the last day of the date's month
+fn last_day_of_month(date: &NaiveDate) -> Option<u8> {
+    Some(first_of_next_month(date)?.pred_opt()?.day() as u8)
+}
+
+// return the day of week (sunday == 0) of another day in the date's month
+fn weekday_of(date: &NaiveDate, day: u8) -> u8 {
+    match date.with_day(day as u32) {
+        Some(other) => other.weekday().num_days_from_sunday() as u8,
+        None => 7,
+    }
+}
+
+// the most days a month can have, counting february as 29
+fn days_in_month(month: u8) -> u8 {
+    match month {
+        2 => 29,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn first_of_next_month(date: &NaiveDate) -> Option<NaiveDate> {
+    if date.month() == 12 {
+        NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)
+    }
+}
+
+impl fmt::Display for RunAt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.describe())
+    }
+}
+
+impl FromStr for RunAt {
+    type Err = anyhow::Error;
+
+    fn from_str(expr: &str) -> Result<RunAt> {
+        RunAt::from_cron(expr)
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use chrono::naive::NaiveDateTime;
@@ -172,6 +1038,424 @@ mod tests {
         assert_eq!(runat.match_datetime(&dt), true);
     }
 
+    #[test]
+    fn from_cron() {
+        let runat = RunAt::from_cron("*/15 9-17 * * 1-5").unwrap();
+
+        // a tuesday
+        let dt = parse_datetime("2022-11-22 09:45:00");
+        assert!(runat.match_datetime(&dt));
+
+        let dt = parse_datetime("2022-11-22 18:00:00");
+        assert!(!runat.match_datetime(&dt));
+
+        // a sunday
+        let dt = parse_datetime("2022-11-20 09:45:00");
+        assert!(!runat.match_datetime(&dt));
+
+        let parsed: RunAt = "*/15 9-17 * * mon-fri".parse().unwrap();
+        assert_eq!(parsed, runat);
+
+        assert!("0 0 32 * *".parse::<RunAt>().is_err());
+    }
+
+    #[test]
+    fn next_after_minutes() {
+        let runat = RunAt::with_minutes(&vec![10u8, 40u8]);
+
+        let dt = parse_datetime("2022-01-01 10:05:30");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-01-01 10:10:00"))
+        );
+
+        // strictly after
+        let dt = parse_datetime("2022-01-01 10:10:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-01-01 10:40:00"))
+        );
+
+        // roll over the hour, day, month and year
+        let dt = parse_datetime("2022-12-31 23:45:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2023-01-01 00:10:00"))
+        );
+    }
+
+    #[test]
+    fn next_after_fields() {
+        let runat = RunAt::from_cron("30 9 * * 1-5").unwrap();
+
+        // friday evening to monday morning
+        let dt = parse_datetime("2022-11-25 17:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-11-28 09:30:00"))
+        );
+
+        let runat = RunAt::from_cron("0 12 29 2 *").unwrap();
+        let dt = parse_datetime("2022-03-01 00:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2024-02-29 12:00:00"))
+        );
+
+        // the 13th or any friday, as in crontab
+        let runat = RunAt::from_cron("0 0 13 * 5").unwrap();
+        let dt = parse_datetime("2022-11-26 00:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-12-02 00:00:00"))
+        );
+        let dt = parse_datetime("2022-12-10 00:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-12-13 00:00:00"))
+        );
+
+        let mut runat = RunAt::from_cron("0 0 1 1 *").unwrap();
+        runat.years = vec![2030u16, 2025u16];
+        let dt = parse_datetime("2022-06-01 00:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2025-01-01 00:00:00"))
+        );
+
+        let dt = parse_datetime("2025-01-01 00:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2030-01-01 00:00:00"))
+        );
+    }
+
+    #[test]
+    fn next_after_never() {
+        let mut runat = RunAt::with_minutes(&vec![0u8]);
+        runat.years = vec![2020u16, 2021u16];
+
+        let dt = parse_datetime("2022-01-01 00:00:00");
+        assert_eq!(runat.next_after(&dt), None);
+
+        // february 30th
+        let runat = RunAt::from_cron("0 0 30 2 *").unwrap();
+        assert_eq!(runat.next_after(&dt), None);
+    }
+
+    #[test]
+    fn seconds() {
+        let mut runat = RunAt::from_cron("* * * * *").unwrap();
+        assert!(runat.match_datetime(&parse_datetime("2022-01-01 10:00:00")));
+        assert!(!runat.match_datetime(&parse_datetime("2022-01-01 10:00:15")));
+
+        let dt = parse_datetime("2022-01-01 10:00:15");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-01-01 10:01:00"))
+        );
+        assert_eq!(
+            runat.prev_before(&dt),
+            Some(parse_datetime("2022-01-01 10:00:00"))
+        );
+
+        runat.seconds = Some(vec![0u8, 15u8, 30u8, 45u8]);
+        assert!(runat.match_datetime(&dt));
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-01-01 10:00:30"))
+        );
+        assert_eq!(
+            runat.prev_before(&dt),
+            Some(parse_datetime("2022-01-01 10:00:00"))
+        );
+
+        let dt = parse_datetime("2022-01-01 10:59:50");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-01-01 11:00:00"))
+        );
+        let list: Vec<NaiveDateTime> = runat.occurrences_before(&dt).take(2).collect();
+        assert_eq!(
+            list,
+            vec![
+                parse_datetime("2022-01-01 10:59:45"),
+                parse_datetime("2022-01-01 10:59:30"),
+            ]
+        );
+
+        // an empty list is every second
+        runat.seconds = Some(Vec::new());
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-01-01 10:59:51"))
+        );
+
+        runat.seconds = Some(vec![60u8]);
+        assert!(runat.is_valid().is_err());
+
+        // serialized values without seconds fire on second 0
+        let json = r#"{"minutes":[5],"hours":[],"days_of_month":[],"days_of_week":[],"months":[],"years":[]}"#;
+        let runat: RunAt = serde_json::from_str(json).unwrap();
+        assert_eq!(runat.seconds, None);
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-01-01 11:05:00"))
+        );
+    }
+
+    #[test]
+    fn day_rules() {
+        let date = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap();
+
+        // the last day of the month
+        assert!(DayRule::LastDayOfMonth(0).matches(&date("2024-02-29")));
+        assert!(!DayRule::LastDayOfMonth(0).matches(&date("2024-02-28")));
+        assert!(DayRule::LastDayOfMonth(2).matches(&date("2022-12-29")));
+
+        // the last weekday; 2022-07-31 is a sunday
+        assert!(DayRule::LastWeekdayOfMonth.matches(&date("2022-07-29")));
+        assert!(!DayRule::LastWeekdayOfMonth.matches(&date("2022-07-31")));
+
+        // 2022-10-15 is a saturday, 2022-05-15 a sunday, 2022-10-01 a saturday, 2022-07-31 a sunday
+        assert!(DayRule::NearestWeekday(15).matches(&date("2022-10-14")));
+        assert!(DayRule::NearestWeekday(15).matches(&date("2022-05-16")));
+        assert!(DayRule::NearestWeekday(1).matches(&date("2022-10-03")));
+        assert!(DayRule::NearestWeekday(31).matches(&date("2022-07-29")));
+        assert!(DayRule::NearestWeekday(10).matches(&date("2022-10-10")));
+        assert!(!DayRule::NearestWeekday(31).matches(&date("2022-06-30")));
+
+        // the last friday and the 2nd tuesday
+        assert!(DayRule::LastDayOfWeek(5).matches(&date("2022-12-30")));
+        assert!(!DayRule::LastDayOfWeek(5).matches(&date("2022-12-23")));
+        let second_tuesday = DayRule::NthDayOfWeek { day: 2, nth: 2 };
+        assert!(second_tuesday.matches(&date("2022-12-13")));
+        assert!(!second_tuesday.matches(&date("2022-12-06")));
+        assert!(!second_tuesday.matches(&date("2022-12-14")));
+    }
+
+    #[test]
+    fn next_after_day_rules() {
+        let mut runat = RunAt::from_cron("0 18 * * *").unwrap();
+        runat.day_rules = vec![DayRule::LastDayOfWeek(5)];
+
+        let dt = parse_datetime("2022-12-01 00:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-12-30 18:00:00"))
+        );
+        assert!(runat.match_datetime(&parse_datetime("2022-12-30 18:00:00")));
+
+        // or'ed with the day list: the 1st or the last day of the month
+        runat.day_rules = vec![DayRule::LastDayOfMonth(0)];
+        runat.days_of_month = vec![1u8];
+        let dt = parse_datetime("2022-02-01 19:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-02-28 18:00:00"))
+        );
+        let dt = parse_datetime("2022-02-28 19:00:00");
+        assert_eq!(
+            runat.next_after(&dt),
+            Some(parse_datetime("2022-03-01 18:00:00"))
+        );
+        assert_eq!(
+            runat.prev_before(&dt),
+            Some(parse_datetime("2022-02-28 18:00:00"))
+        );
+
+        runat.days_of_month = vec![31u8];
+        runat.months = vec![2u8];
+        assert!(runat.is_valid().is_ok());
+
+        runat.day_rules = vec![DayRule::NthDayOfWeek { day: 2, nth: 6 }];
+        assert!(runat.is_valid().is_err());
+    }
+
+    #[test]
+    fn describe() {
+        let mut runat = RunAt::with_minutes(&vec![10u8, 20u8, 30u8]);
+        runat.hours = vec![10u8, 20u8, 0u8, 1u8];
+        assert_eq!(
+            runat.describe(),
+            "at minutes 10, 20 and 30 past hours 0, 1, 10 and 20, every day"
+        );
+        assert_eq!(runat.to_string(), runat.describe());
+
+        let runat = RunAt::from_cron("*/15 9-17 * * 1-5").unwrap();
+        assert_eq!(
+            runat.describe(),
+            "at minutes 0, 15, 30 and 45 past hours 9 through 17, on monday, tuesday, wednesday, thursday or friday"
+        );
+
+        let runat = RunAt::from_cron("CRON_TZ=America/Los_Angeles 30 9 1,L jan,jul *").unwrap();
+        assert_eq!(
+            runat.describe(),
+            "at 09:30, on day 1 of the month or the last day of the month, in january and july (America/Los_Angeles)"
+        );
+
+        let runat = RunAt::from_cron("0 18 * * 5L,2#2").unwrap();
+        assert_eq!(
+            runat.describe(),
+            "at 18:00, on the last friday of the month or the 2nd tuesday of the month"
+        );
+
+        let runat = RunAt::from_cron("0 0 13 * 5").unwrap();
+        assert_eq!(
+            runat.describe(),
+            "at 00:00, on day 13 of the month or friday"
+        );
+
+        let runat = RunAt::from_cron("*/10 * 9 * * *").unwrap();
+        assert_eq!(
+            runat.describe(),
+            "at seconds 0, 10, 20, 30, 40 and 50 every minute of hour 9, every day"
+        );
+
+        assert_eq!(RunAt::new().describe(), "every minute, every day");
+
+        let runat = RunAt::from_cron("0 0 12 1 1 * 2030").unwrap();
+        assert_eq!(
+            runat.describe(),
+            "at 12:00, on day 1 of the month, in january, in 2030"
+        );
+        assert_eq!(runat.to_cron_string(), "0 0 12 1 1 * 2030");
+    }
+
+    #[test]
+    fn prev_before() {
+        let runat = RunAt::from_cron("30 9 * * 1-5").unwrap();
+
+        // monday morning back to friday
+        let dt = parse_datetime("2022-11-28 09:00:00");
+        assert_eq!(
+            runat.prev_before(&dt),
+            Some(parse_datetime("2022-11-25 09:30:00"))
+        );
+
+        // strictly before
+        let dt = parse_datetime("2022-11-28 09:30:00");
+        assert_eq!(
+            runat.prev_before(&dt),
+            Some(parse_datetime("2022-11-25 09:30:00"))
+        );
+
+        let dt = parse_datetime("2022-11-28 09:30:01");
+        assert_eq!(
+            runat.prev_before(&dt),
+            Some(parse_datetime("2022-11-28 09:30:00"))
+        );
+
+        let mut runat = RunAt::with_minutes(&vec![0u8]);
+        runat.years = vec![2030u16];
+        assert_eq!(runat.prev_before(&dt), None);
+    }
+
+    #[test]
+    fn occurrences() {
+        let runat = RunAt::from_cron("0,30 23 * * *").unwrap();
+
+        let start = parse_datetime("2022-12-30 23:30:00");
+        let list: Vec<NaiveDateTime> = runat.occurrences(&start).take(4).collect();
+        assert_eq!(
+            list,
+            vec![
+                parse_datetime("2022-12-30 23:30:00"),
+                parse_datetime("2022-12-31 23:00:00"),
+                parse_datetime("2022-12-31 23:30:00"),
+                parse_datetime("2023-01-01 23:00:00"),
+            ]
+        );
+
+        let end = parse_datetime("2023-01-01 23:00:00");
+        let list: Vec<NaiveDateTime> = runat.occurrences_before(&end).take(3).collect();
+        assert_eq!(
+            list,
+            vec![
+                parse_datetime("2022-12-31 23:30:00"),
+                parse_datetime("2022-12-31 23:00:00"),
+                parse_datetime("2022-12-30 23:30:00"),
+            ]
+        );
+
+        let mut runat = RunAt::from_cron("0 0 1 * *").unwrap();
+        runat.years = vec![2022u16];
+        assert_eq!(runat.occurrences(&start).count(), 0);
+        assert_eq!(runat.occurrences_before(&start).count(), 12);
+    }
+
+    fn parse_utc(value: &str) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&parse_datetime(value))
+    }
+
+    #[test]
+    fn next_fire_in_time_zone() {
+        let mut runat = RunAt::from_cron("0 9 * * *").unwrap();
+        runat.timezone = Some("America/Los_Angeles".to_string());
+
+        // 9am pacific standard time is 17:00 utc
+        let after = parse_utc("2022-12-01 12:00:00");
+        assert_eq!(
+            runat.next_fire(&after),
+            Some(parse_utc("2022-12-01 17:00:00"))
+        );
+        assert!(runat.matches_at(&parse_utc("2022-12-01 17:00:00")));
+        assert!(!runat.matches_at(&parse_utc("2022-12-01 09:00:00")));
+
+        // and 16:00 utc during daylight time
+        let after = parse_utc("2022-07-01 12:00:00");
+        assert_eq!(
+            runat.next_fire(&after),
+            Some(parse_utc("2022-07-01 16:00:00"))
+        );
+
+        runat.timezone = Some("Mars/Olympus_Mons".to_string());
+        assert_eq!(runat.next_fire(&after), None);
+        let err = runat.is_valid().unwrap_err();
+        assert_eq!(
+            err.problems,
+            vec![RunAtProblem::UnknownTimeZone(
+                "Mars/Olympus_Mons".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn next_fire_skipped_local_time() {
+        // 2022-03-13 02:00 pacific jumps to 03:00 (10:00 utc)
+        let mut runat = RunAt::from_cron("30 2 * * *").unwrap();
+        runat.timezone = Some("America/Los_Angeles".to_string());
+
+        let after = parse_utc("2022-03-12 12:00:00");
+        let fired = runat.next_fire(&after).unwrap();
+        assert_eq!(fired, parse_utc("2022-03-13 10:00:00"));
+
+        let fired = runat.next_fire(&fired).unwrap();
+        assert_eq!(fired, parse_utc("2022-03-14 09:30:00"));
+
+        // every minute in the skipped hour collapses to a single run at the transition
+        let mut runat = RunAt::from_cron("* 2 * * *").unwrap();
+        runat.timezone = Some("America/Los_Angeles".to_string());
+        let fired = runat.next_fire(&after).unwrap();
+        assert_eq!(fired, parse_utc("2022-03-13 10:00:00"));
+        let fired = runat.next_fire(&fired).unwrap();
+        assert_eq!(fired, parse_utc("2022-03-14 09:00:00"));
+    }
+
+    #[test]
+    fn next_fire_repeated_local_time() {
+        // 2022-11-06 01:00..02:00 pacific happens twice (08:00 and 09:00 utc)
+        let mut runat = RunAt::from_cron("30 1 * * *").unwrap();
+        runat.timezone = Some("America/Los_Angeles".to_string());
+
+        let after = parse_utc("2022-11-06 06:00:00");
+        let fired = runat.next_fire(&after).unwrap();
+        assert_eq!(fired, parse_utc("2022-11-06 08:30:00"));
+
+        let fired = runat.next_fire(&fired).unwrap();
+        assert_eq!(fired, parse_utc("2022-11-07 09:30:00"));
+    }
+
     #[test]
     fn new() {
         let runat = RunAt::new();
@@ -183,7 +1467,55 @@ mod tests {
         assert!(runat.months.is_empty());
         assert!(runat.years.is_empty());
 
-        assert!(runat.is_valid())
+        assert!(runat.is_valid().is_ok())
+    }
+
+    #[test]
+    fn is_valid_ranges() {
+        let mut runat = RunAt::from_cron("0 12 * * *").unwrap();
+        runat.minutes = vec![0u8, 60u8];
+        runat.days_of_week = vec![8u8];
+        runat.months = vec![0u8];
+
+        let err = runat.is_valid().unwrap_err();
+        assert_eq!(err.problems.len(), 3);
+        assert_eq!(
+            err.problems[0],
+            RunAtProblem::OutOfRange {
+                field: "minutes",
+                value: 60,
+                min: 0,
+                max: 59
+            }
+        );
+        assert!(err.to_string().contains("days_of_week value 8"));
+        assert!(err.to_string().contains("months value 0"));
+
+        let mut runat = RunAt::new();
+        runat.years = vec![3000u16];
+        assert!(runat.is_valid().is_err());
+        assert!(!runat.valid_year(3000u16));
+    }
+
+    #[test]
+    fn is_valid_never_matches() {
+        let runat = RunAt::from_cron("0 0 30,31 2 *").unwrap();
+        let err = runat.is_valid().unwrap_err();
+        assert!(matches!(err.problems[0], RunAtProblem::NeverMatches(_)));
+
+        let mut runat = RunAt::from_cron("0 0 1 1 *").unwrap();
+        runat.years = vec![2001u16, 2002u16];
+        let err = runat.is_valid().unwrap_err();
+        assert_eq!(
+            err.problems,
+            vec![RunAtProblem::YearsInPast(vec![2001u16, 2002u16])]
+        );
+
+        // february 29th in a year that is not a leap year
+        let mut runat = RunAt::from_cron("0 0 29 2 *").unwrap();
+        assert!(runat.is_valid().is_ok());
+        runat.years = vec![2999u16];
+        assert!(runat.is_valid().is_err());
     }
 
     #[test]
@@ -199,7 +1531,7 @@ mod tests {
         assert!(runat.months.is_empty());
         assert!(runat.years.is_empty());
 
-        assert!(runat.is_valid())
+        assert!(runat.is_valid().is_ok())
     }
 
     #[test]
@@ -216,6 +1548,6 @@ mod tests {
         assert!(runat.months.is_empty());
         assert!(runat.years.is_empty());
 
-        assert!(runat.is_valid())
+        assert!(runat.is_valid().is_ok())
     }
 }
@@ -0,0 +1,173 @@
+/// JobRun - the record of a single execution of a job, kept apart from the job definition so
+/// every run of a recurring job keeps its own output.
+use crate::models::jobs::Job;
+use crate::models::results::Results;
+use chrono::{DateTime, Utc};
+use domain_keys::keys::TimeStampKey;
+use domain_keys::models::{Model, Status};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRun {
+    pub id: String,
+    pub job_key: String,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    pub status: Status, // Active while running, then Processed or Blocked as set on the job
+    #[serde(default)]
+    pub pid: Option<u64>,
+    #[serde(default)]
+    pub exit_code: Option<u8>, // the process's own exit code; None if it was killed or never started
+    pub log: Vec<String>,
+    pub errors: Vec<String>,
+    pub results: Option<Results>,
+}
+
+impl JobRun {
+    /// create a new run of the job starting now
+    pub fn new(job_key: &str) -> JobRun {
+        JobRun {
+            id: TimeStampKey::create(),
+            job_key: job_key.to_string(),
+            started: Utc::now(),
+            finished: None,
+            status: Status::Active(0),
+            pid: None,
+            exit_code: None,
+            log: Vec::new(),
+            errors: Vec::new(),
+            results: None,
+        }
+    }
+
+    /// copy the status, pid and output of the run's copy of the job into the run
+    pub fn update(&mut self, model: &Model<Job>) {
+        self.status = model.status.clone();
+        self.pid = model.value.pid;
+        self.log = model.value.log.clone();
+        self.errors = model.value.errors.clone();
+        self.results = model.value.results.clone();
+    }
+
+    /// copy the job's status and output into the run and mark it finished now
+    pub fn finish(&mut self, model: &Model<Job>) {
+        self.update(model);
+        self.finished = Some(Utc::now());
+    }
+}
+
+/// RunUpdate - the fields of a job that its run changes: status, pid, output, results and
+/// progress.  the store applies it to the job's current model, so edits made to the job while it
+/// runs are kept.  only the run that owns the job updates it: the run named by the job's `run_id`,
+/// or any run once that one is over, so a parallel run can't overwrite the active run's fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunUpdate {
+    pub key: String,
+    pub run_id: Option<String>,
+    pub status: Status,
+    pub pid: Option<u64>,
+    pub log: Vec<String>,
+    pub errors: Vec<String>,
+    pub results: Option<Results>,
+    pub progress: Option<String>,
+}
+
+impl RunUpdate {
+    /// take the run's fields from the executor's copy of the job
+    pub fn new(model: &Model<Job>) -> RunUpdate {
+        RunUpdate {
+            key: model.key.to_string(),
+            run_id: model.value.run_id.clone(),
+            status: model.status.clone(),
+            pid: model.value.pid,
+            log: model.value.log.clone(),
+            errors: model.value.errors.clone(),
+            results: model.value.results.clone(),
+            progress: model.value.progress.clone(),
+        }
+    }
+
+    /// return the current model with the run's fields set, None if another run owns the job
+    pub fn apply(&self, current: &Model<Job>) -> Option<Model<Job>> {
+        if current.value.run_id != self.run_id && is_running(&current.status) {
+            return None;
+        }
+
+        let mut model = current.clone();
+        model.value.run_id = self.run_id.clone();
+        model.value.pid = self.pid;
+        model.value.log = self.log.clone();
+        model.value.errors = self.errors.clone();
+        model.value.results = self.results.clone();
+        model.value.progress = self.progress.clone();
+
+        Some(Job::update_model(&model, self.status.clone()))
+    }
+}
+
+/// true while a run is in progress: Active, Inactive when paused, or New(128) when queued
+fn is_running(status: &Status) -> bool {
+    match status {
+        Status::Active(_) | Status::Inactive(_) => true,
+        Status::New(code) => *code > 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finish() {
+        let mut model = Job::create_model(&Job::new("report", "report.sh"));
+        let mut run = JobRun::new(&model.key);
+        assert_eq!(run.status, Status::Active(0));
+        assert_eq!(run.finished, None);
+
+        model.status = Status::Processed(0);
+        model.value.log.push("done".to_string());
+        run.finish(&model);
+
+        assert_eq!(run.status, Status::Processed(0));
+        assert_eq!(run.log, vec!["done"]);
+        assert!(run.finished.unwrap() >= run.started);
+
+        let json = serde_json::to_string(&run).unwrap();
+        let copy: JobRun = serde_json::from_str(&json).unwrap();
+        assert_eq!(copy, run);
+    }
+
+    #[test]
+    fn update() {
+        let model = Job::create_model(&Job::new("report", "report.sh"));
+        let mut running = Job::update_model(&model, Status::Active(3));
+        running.value.pid = Some(42);
+        running.value.log.push("working".to_string());
+        let update = RunUpdate::new(&running);
+
+        // the job was edited while it ran; the edit is kept
+        let mut current = model.clone();
+        current.value.action = crate::models::action::Action::from("report.sh --all");
+        let updated = update.apply(&current).unwrap();
+
+        assert_eq!(updated.status, Status::Active(3));
+        assert_eq!(updated.value.pid, Some(42));
+        assert_eq!(updated.value.log, vec!["working"]);
+        assert_eq!(updated.value.action, current.value.action);
+        assert_ne!(updated.version, current.version);
+
+        // a parallel run doesn't take over the active run's fields, only a finished job
+        let mut first = Job::update_model(&model, Status::Active(0));
+        first.value.run_id = Some("first".to_string());
+        let mut second = running.clone();
+        second.value.run_id = Some("second".to_string());
+        let update = RunUpdate::new(&second);
+        assert_eq!(update.apply(&first), None);
+        let first = Job::update_model(&first, Status::Processed(0));
+        assert_eq!(
+            update.apply(&first).unwrap().value.run_id,
+            Some("second".to_string())
+        );
+    }
+}
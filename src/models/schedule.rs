@@ -0,0 +1,302 @@
+/// Schedule - when a job should run: a cron like RunAt, once at a timestamp, at a fixed interval
+/// or after a delay.
+///
+/// serialized untagged so a plain RunAt object (the original `run_at` format) is still accepted.
+use crate::models::run_at::RunAt;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Schedule {
+    /// run a single time at the timestamp, e.g. {"once": "2022-12-25T08:00:00Z"}
+    Once { once: DateTime<Utc> },
+    /// run every `every_seconds` starting at `start`, e.g. {"every_seconds": 300, "start": "..."}
+    Interval {
+        every_seconds: u64,
+        start: DateTime<Utc>,
+    },
+    /// run a single time `delay_seconds` after `from`; `from` defaults to the time it was read
+    Delay {
+        delay_seconds: u64,
+        #[serde(default = "Utc::now")]
+        from: DateTime<Utc>,
+    },
+    /// a cron like calendar pattern
+    Cron(RunAt),
+}
+
+impl Schedule {
+    /// create a schedule that runs once at the timestamp
+    pub fn once(at: DateTime<Utc>) -> Schedule {
+        Schedule::Once { once: at }
+    }
+
+    /// create a schedule that runs every `seconds` starting at `start`
+    pub fn every_seconds(seconds: u64, start: DateTime<Utc>) -> Schedule {
+        Schedule::Interval {
+            every_seconds: seconds,
+            start,
+        }
+    }
+
+    /// create a schedule that runs every `minutes` starting at `start`
+    pub fn every_minutes(minutes: u64, start: DateTime<Utc>) -> Schedule {
+        Schedule::every_seconds(minutes * 60, start)
+    }
+
+    /// create a schedule that runs once, `seconds` from now
+    pub fn after_delay(seconds: u64) -> Schedule {
+        Schedule::Delay {
+            delay_seconds: seconds,
+            from: Utc::now(),
+        }
+    }
+
+    /// return the first instant strictly after `after` that this schedule fires, or None if it
+    /// will not fire again
+    pub fn next_fire(&self, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Once { once } => Some(*once).filter(|at| at > after),
+            Schedule::Delay {
+                delay_seconds,
+                from,
+            } => Some(*from + seconds(*delay_seconds)?).filter(|at| at > after),
+            Schedule::Interval {
+                every_seconds,
+                start,
+            } => {
+                if *every_seconds == 0 {
+                    return None;
+                }
+
+                if start > after {
+                    return Some(*start);
+                }
+
+                let every = seconds(*every_seconds)?.num_milliseconds();
+                let elapsed = (*after - *start).num_milliseconds();
+                let count = elapsed / every + 1;
+
+                Some(*start + Duration::milliseconds(every.checked_mul(count)?))
+            }
+            Schedule::Cron(run_at) => run_at.next_fire(after),
+        }
+    }
+
+    /// return the instant the schedule's runs are counted from, just before its first fire: the
+    /// `once` time, the delay's `from` or the interval's `start`; None for a cron pattern
+    pub fn start(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Once { once } => Some(*once - Duration::nanoseconds(1)),
+            Schedule::Delay { from, .. } => Some(*from),
+            Schedule::Interval { start, .. } => Some(*start - Duration::nanoseconds(1)),
+            Schedule::Cron(_) => None,
+        }
+    }
+
+    /// return an english description of the schedule for listings and api responses
+    pub fn describe(&self) -> String {
+        const FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+        match self {
+            Schedule::Once { once } => format!("once at {}", once.format(FORMAT)),
+            Schedule::Interval {
+                every_seconds,
+                start,
+            } => {
+                let every = if every_seconds % 3600 == 0 {
+                    format!("{} hours", every_seconds / 3600)
+                } else if every_seconds % 60 == 0 {
+                    format!("{} minutes", every_seconds / 60)
+                } else {
+                    format!("{} seconds", every_seconds)
+                };
+                format!("every {} starting at {}", every, start.format(FORMAT))
+            }
+            Schedule::Delay {
+                delay_seconds,
+                from,
+            } => format!(
+                "once, {} seconds after {}",
+                delay_seconds,
+                from.format(FORMAT)
+            ),
+            Schedule::Cron(run_at) => run_at.describe(),
+        }
+    }
+
+    /// return the calendar date of the instant; local to the time zone for cron schedules, else utc
+    pub fn local_date(&self, instant: &DateTime<Utc>) -> Option<NaiveDate> {
+        match self {
+            Schedule::Cron(run_at) => run_at.local_datetime(instant).map(|dt| dt.date()),
+            _ => Some(instant.naive_utc().date()),
+        }
+    }
+
+    /// return the instant that the date starts; the inverse of `local_date`
+    pub fn start_of_day(&self, date: &NaiveDate) -> Option<DateTime<Utc>> {
+        let midnight = date.and_hms_opt(0, 0, 0)?;
+        match self {
+            Schedule::Cron(run_at) => run_at.local_to_utc(&midnight),
+            _ => Some(Utc.from_utc_datetime(&midnight)),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.describe())
+    }
+}
+
+impl From<RunAt> for Schedule {
+    fn from(run_at: RunAt) -> Schedule {
+        Schedule::Cron(run_at)
+    }
+}
+
+fn seconds(value: u64) -> Option<Duration> {
+    i64::try_from(value).ok().map(Duration::seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn once() {
+        let at = parse_utc("2022-12-25T08:00:00Z");
+        let schedule = Schedule::once(at);
+
+        assert_eq!(
+            schedule.next_fire(&parse_utc("2022-12-24T00:00:00Z")),
+            Some(at)
+        );
+        assert_eq!(schedule.next_fire(&at), None);
+    }
+
+    #[test]
+    fn delay() {
+        let schedule = Schedule::Delay {
+            delay_seconds: 90,
+            from: parse_utc("2022-12-25T08:00:00Z"),
+        };
+
+        let after = parse_utc("2022-12-25T08:00:00Z");
+        assert_eq!(
+            schedule.next_fire(&after),
+            Some(parse_utc("2022-12-25T08:01:30Z"))
+        );
+        assert_eq!(schedule.next_fire(&parse_utc("2022-12-25T08:01:30Z")), None);
+
+        let schedule = Schedule::after_delay(60);
+        assert!(schedule.next_fire(&Utc::now()).is_some());
+
+        // the first fire comes after the start for each kind of schedule
+        let at = parse_utc("2022-12-25T08:00:00Z");
+        for schedule in [Schedule::once(at), Schedule::every_minutes(15, at)] {
+            assert_eq!(schedule.next_fire(&schedule.start().unwrap()), Some(at));
+        }
+        assert_eq!(
+            Schedule::Cron(RunAt::with_minutes(&vec![0u8])).start(),
+            None
+        );
+    }
+
+    #[test]
+    fn interval() {
+        let start = parse_utc("2022-12-25T08:00:00Z");
+        let schedule = Schedule::every_minutes(15, start);
+
+        let after = parse_utc("2022-12-25T07:00:00Z");
+        assert_eq!(schedule.next_fire(&after), Some(start));
+        assert_eq!(
+            schedule.next_fire(&start),
+            Some(parse_utc("2022-12-25T08:15:00Z"))
+        );
+
+        let after = parse_utc("2022-12-25T09:07:30Z");
+        assert_eq!(
+            schedule.next_fire(&after),
+            Some(parse_utc("2022-12-25T09:15:00Z"))
+        );
+
+        let schedule = Schedule::every_seconds(0, start);
+        assert_eq!(schedule.next_fire(&after), None);
+    }
+
+    #[test]
+    fn cron() {
+        let mut run_at = RunAt::from_cron("0 9 * * *").unwrap();
+        run_at.timezone = Some("UTC".to_string());
+        let schedule = Schedule::from(run_at);
+
+        let after = parse_utc("2022-12-25T10:00:00Z");
+        assert_eq!(
+            schedule.next_fire(&after),
+            Some(parse_utc("2022-12-26T09:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn describe() {
+        let start = parse_utc("2022-12-25T08:00:00Z");
+        assert_eq!(
+            Schedule::once(start).to_string(),
+            "once at 2022-12-25 08:00:00 UTC"
+        );
+        assert_eq!(
+            Schedule::every_minutes(15, start).to_string(),
+            "every 15 minutes starting at 2022-12-25 08:00:00 UTC"
+        );
+
+        let schedule = Schedule::Delay {
+            delay_seconds: 90,
+            from: start,
+        };
+        assert_eq!(
+            schedule.to_string(),
+            "once, 90 seconds after 2022-12-25 08:00:00 UTC"
+        );
+
+        let schedule = Schedule::from(RunAt::from_cron("0 9 * * *").unwrap());
+        assert_eq!(schedule.to_string(), "at 09:00, every day");
+    }
+
+    #[test]
+    fn serialize() {
+        let json = r#"{"once":"2022-12-25T08:00:00Z"}"#;
+        let schedule: Schedule = serde_json::from_str(json).unwrap();
+        assert_eq!(schedule, Schedule::once(parse_utc("2022-12-25T08:00:00Z")));
+        assert_eq!(serde_json::to_string(&schedule).unwrap(), json);
+
+        let json = r#"{"every_seconds":30,"start":"2022-12-25T08:00:00Z"}"#;
+        let schedule: Schedule = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            schedule,
+            Schedule::every_seconds(30, parse_utc("2022-12-25T08:00:00Z"))
+        );
+
+        let json = r#"{"delay_seconds":300}"#;
+        let schedule: Schedule = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            schedule,
+            Schedule::Delay {
+                delay_seconds: 300,
+                ..
+            }
+        ));
+
+        // the original run at format
+        let json = r#"{"minutes":[5],"hours":[],"days_of_month":[],"days_of_week":[],"months":[],"years":[]}"#;
+        let schedule: Schedule = serde_json::from_str(json).unwrap();
+        assert_eq!(schedule, Schedule::Cron(RunAt::with_minutes(&vec![5u8])));
+    }
+}
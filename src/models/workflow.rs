@@ -0,0 +1,293 @@
+/// Workflow - the dependency graph of jobs built from each job's `depends_on` keys.  A valid
+/// workflow has no cycles and no dependencies on unknown jobs; `order` only checks for cycles, so
+/// a dependency can be added before the job it names exists.
+use crate::models::jobs::Job;
+use anyhow::{anyhow, Result};
+use domain_keys::models::Model;
+use hashbrown::{HashMap, HashSet};
+use std::collections::VecDeque;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Workflow {
+    dependencies: HashMap<String, Vec<String>>, // job key -> the jobs it waits on
+    dependents: HashMap<String, Vec<String>>,   // job key -> the jobs waiting on it
+}
+
+impl Workflow {
+    /// build the workflow from the jobs without validating it
+    pub fn new<'a, I>(jobs: I) -> Workflow
+    where
+        I: IntoIterator<Item = &'a Model<Job>>,
+    {
+        let mut workflow = Workflow::default();
+        for model in jobs {
+            workflow
+                .dependencies
+                .insert(model.key.to_string(), model.value.depends_on.clone());
+        }
+
+        for (key, dependencies) in workflow.dependencies.iter() {
+            for dependency in dependencies {
+                workflow
+                    .dependents
+                    .entry(dependency.to_string())
+                    .or_default()
+                    .push(key.to_string());
+            }
+        }
+
+        // sorted for a stable trigger order
+        for dependents in workflow.dependents.values_mut() {
+            dependents.sort();
+            dependents.dedup();
+        }
+
+        workflow
+    }
+
+    /// build and validate the workflow from the jobs
+    pub fn from_jobs<'a, I>(jobs: I) -> Result<Workflow>
+    where
+        I: IntoIterator<Item = &'a Model<Job>>,
+    {
+        let workflow = Workflow::new(jobs);
+        workflow.validate()?;
+
+        Ok(workflow)
+    }
+
+    /// return an error if a job depends on an unknown job or the graph has a cycle
+    pub fn validate(&self) -> Result<()> {
+        let mut keys: Vec<&String> = self.dependencies.keys().collect();
+        keys.sort();
+        for key in keys {
+            if let Some(unknown) = self
+                .dependencies(key)
+                .iter()
+                .find(|dependency| !self.dependencies.contains_key(*dependency))
+            {
+                return Err(anyhow!("job {} depends on unknown job {}", key, unknown));
+            }
+        }
+
+        self.order().map(|_| ())
+    }
+
+    /// return an error if the job can't join the other jobs: it has both dependencies and a
+    /// `run_at` schedule, or its dependencies close a cycle, which the error names.  the other
+    /// jobs are taken to be valid, so a new cycle has to pass through the job.
+    pub fn check_job<'a, I>(jobs: I, model: &'a Model<Job>) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Model<Job>>,
+    {
+        if model.value.depends_on.is_empty() {
+            return Ok(());
+        }
+
+        if model.value.run_at.is_some() {
+            return Err(anyhow!(
+                "job {} has dependencies, so it is run by them and can't have a run_at schedule",
+                model.key
+            ));
+        }
+
+        let others = jobs.into_iter().filter(|job| job.key != model.key);
+        let workflow = Workflow::new(others.chain(std::iter::once(model)));
+        match workflow.cycle_through(&model.key) {
+            Some(cycle) => Err(anyhow!("dependency cycle: {}", cycle.join(" -> "))),
+            None => Ok(()),
+        }
+    }
+
+    /// return the shortest dependency path from the key back to itself, starting and ending with
+    /// the key; None if the key is not part of a cycle
+    pub fn cycle_through(&self, key: &str) -> Option<Vec<String>> {
+        let mut reached_from: HashMap<&str, &str> = HashMap::new();
+        let mut queue: VecDeque<&str> = VecDeque::from(vec![key]);
+
+        while let Some(next) = queue.pop_front() {
+            for dependency in self.dependencies(next) {
+                if dependency == key {
+                    // walk back from the last job to the key
+                    let mut cycle = vec![key.to_string()];
+                    let mut at = next;
+                    while at != key {
+                        cycle.push(at.to_string());
+                        at = reached_from[at];
+                    }
+                    cycle.push(key.to_string());
+                    cycle.reverse();
+
+                    return Some(cycle);
+                }
+
+                if !reached_from.contains_key(dependency.as_str()) {
+                    reached_from.insert(dependency, next);
+                    queue.push_back(dependency);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// return the jobs the key waits on
+    pub fn dependencies(&self, key: &str) -> &[String] {
+        self.dependencies
+            .get(key)
+            .map_or(&[], |list| list.as_slice())
+    }
+
+    /// return the jobs waiting on the key
+    pub fn dependents(&self, key: &str) -> &[String] {
+        self.dependents.get(key).map_or(&[], |list| list.as_slice())
+    }
+
+    /// return every job downstream of the key, nearest first
+    pub fn downstream(&self, key: &str) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut list = Vec::new();
+        let mut queue: VecDeque<&str> = VecDeque::from(vec![key]);
+
+        while let Some(next) = queue.pop_front() {
+            for dependent in self.dependents(next) {
+                if seen.insert(dependent.as_str()) {
+                    list.push(dependent.to_string());
+                    queue.push_back(dependent);
+                }
+            }
+        }
+
+        list
+    }
+
+    /// return the job keys in dependency order, upstream jobs first; an error if there is a cycle
+    pub fn order(&self) -> Result<Vec<String>> {
+        let mut waiting: HashMap<&str, usize> = self
+            .dependencies
+            .iter()
+            .map(|(key, deps)| {
+                // unknown jobs are not in the graph, so they don't hold up the order
+                let known: HashSet<&String> = deps
+                    .iter()
+                    .filter(|dep| self.dependencies.contains_key(*dep))
+                    .collect();
+                (key.as_str(), known.len())
+            })
+            .collect();
+        let mut ready: Vec<&str> = waiting
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(key, _)| *key)
+            .collect();
+        ready.sort_unstable();
+
+        let mut order = Vec::with_capacity(waiting.len());
+        while let Some(key) = ready.pop() {
+            order.push(key.to_string());
+            for dependent in self.dependents(key) {
+                if let Some(count) = waiting.get_mut(dependent.as_str()) {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push(dependent);
+                    }
+                }
+            }
+        }
+
+        if order.len() < self.dependencies.len() {
+            let mut cycle: Vec<&str> = waiting
+                .iter()
+                .filter(|(_, count)| **count > 0)
+                .map(|(key, _)| *key)
+                .collect();
+            cycle.sort_unstable();
+
+            return Err(anyhow!(
+                "dependency cycle between jobs: {}",
+                cycle.join(", ")
+            ));
+        }
+
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(key: &str, depends_on: &[&str]) -> Model<Job> {
+        let mut model = Job::create_model(&Job::new(key, "no-op"));
+        model.key = key.to_string();
+        model.value.depends_on = depends_on.iter().map(|k| k.to_string()).collect();
+        model
+    }
+
+    #[test]
+    fn fan_out_fan_in() {
+        // extract -> (clean, stats) -> load -> report
+        let jobs = vec![
+            model("extract", &[]),
+            model("clean", &["extract"]),
+            model("stats", &["extract"]),
+            model("load", &["clean", "stats"]),
+            model("report", &["load"]),
+        ];
+        let workflow = Workflow::from_jobs(&jobs).unwrap();
+
+        assert_eq!(workflow.dependents("extract"), &["clean", "stats"]);
+        assert_eq!(workflow.dependencies("load"), &["clean", "stats"]);
+        assert!(workflow.dependents("report").is_empty());
+        assert_eq!(
+            workflow.downstream("extract"),
+            vec!["clean", "stats", "load", "report"]
+        );
+
+        let order = workflow.order().unwrap();
+        let position = |key: &str| order.iter().position(|k| k == key).unwrap();
+        assert_eq!(position("extract"), 0);
+        assert!(position("clean") < position("load"));
+        assert!(position("stats") < position("load"));
+        assert_eq!(position("report"), 4);
+    }
+
+    #[test]
+    fn invalid() {
+        let jobs = vec![
+            model("a", &["c"]),
+            model("b", &["a"]),
+            model("c", &["b"]),
+            model("d", &[]),
+        ];
+        let error = Workflow::from_jobs(&jobs).unwrap_err();
+        assert_eq!(error.to_string(), "dependency cycle between jobs: a, b, c");
+
+        let jobs = vec![model("a", &["a"])];
+        assert!(Workflow::from_jobs(&jobs).is_err());
+
+        let jobs = vec![model("a", &["missing"]), model("b", &["a"])];
+        let error = Workflow::from_jobs(&jobs).unwrap_err();
+        assert_eq!(error.to_string(), "job a depends on unknown job missing");
+
+        // the order doesn't need the missing job
+        let workflow = Workflow::new(&jobs);
+        assert_eq!(workflow.order().unwrap(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn check_job() {
+        let jobs = vec![model("a", &[]), model("b", &["a"]), model("c", &["b"])];
+        assert!(Workflow::check_job(&jobs, &model("d", &["c"])).is_ok());
+
+        // an update that closes a cycle names it
+        let error = Workflow::check_job(&jobs, &model("a", &["c"])).unwrap_err();
+        assert_eq!(error.to_string(), "dependency cycle: a -> c -> b -> a");
+        let error = Workflow::check_job(&jobs, &model("a", &["a"])).unwrap_err();
+        assert_eq!(error.to_string(), "dependency cycle: a -> a");
+
+        let mut scheduled = model("d", &["c"]);
+        scheduled.value.run_at = Some(crate::models::schedule::Schedule::after_delay(60));
+        assert!(Workflow::check_job(&jobs, &scheduled).is_err());
+    }
+}
@@ -0,0 +1,491 @@
+/// OutputSpool.  Spools each run's stdout and stderr to files in a folder per job under the output
+/// folder, one `<job key>/<run id>.log` and `.err` per run, while the job model keeps only a tail
+/// of the last lines.  When lines are dropped from the tail, its first line is a marker with the
+/// number of lines dropped and where to find the full output.
+///
+/// only the newest finished runs of each job are kept; older runs are deleted as runs finish, the
+/// output of runs still in progress is never deleted, and a job's folder is deleted when the job
+/// is removed.
+use anyhow::{anyhow, Result};
+use hashbrown::{HashMap, HashSet};
+use log::warn;
+use std::cmp::Reverse;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// the lines of each stream kept on the job model when no tail is configured
+pub const DEFAULT_OUTPUT_TAIL_LINES: usize = 200;
+/// the bytes of each stream kept on the job model when no tail is configured
+pub const DEFAULT_OUTPUT_TAIL_BYTES: usize = 64 * 1024;
+/// the runs of each job whose spooled output is kept when no retention is configured
+pub const DEFAULT_OUTPUT_RETENTION_RUNS: usize = 100;
+
+/// Stream - which output of the process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Log,    // stdout, kept in Job.log
+    Errors, // stderr, kept in Job.errors
+}
+
+impl Stream {
+    fn extension(&self) -> &'static str {
+        match self {
+            Stream::Log => "log",
+            Stream::Errors => "err",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputSpool {
+    folder: Option<PathBuf>,
+    tail_lines: usize,
+    tail_bytes: usize,
+    retention_runs: usize,
+    active: Arc<Mutex<HashSet<String>>>, // ids of the runs whose output is still open
+}
+
+impl Default for OutputSpool {
+    fn default() -> OutputSpool {
+        OutputSpool::new(DEFAULT_OUTPUT_TAIL_LINES, DEFAULT_OUTPUT_TAIL_BYTES)
+    }
+}
+
+impl OutputSpool {
+    /// keep only the tail in memory, nothing is spooled
+    pub fn new(tail_lines: usize, tail_bytes: usize) -> OutputSpool {
+        OutputSpool {
+            folder: None,
+            tail_lines: tail_lines.max(1),
+            tail_bytes: tail_bytes.max(1),
+            retention_runs: DEFAULT_OUTPUT_RETENTION_RUNS,
+            active: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// spool the full output to files in the folder and keep the tail in memory
+    pub fn with_folder(folder: &Path, tail_lines: usize, tail_bytes: usize) -> OutputSpool {
+        OutputSpool {
+            folder: Some(folder.to_path_buf()),
+            ..OutputSpool::new(tail_lines, tail_bytes)
+        }
+    }
+
+    /// keep the spooled output of the newest `runs` runs of each job
+    pub fn with_retention(self, runs: usize) -> OutputSpool {
+        OutputSpool {
+            retention_runs: runs.max(1),
+            ..self
+        }
+    }
+
+    /// return the folder that holds the job's spooled output, if output is spooled
+    pub fn job_folder(&self, key: &str) -> Option<PathBuf> {
+        self.folder.as_ref().map(|folder| folder.join(key))
+    }
+
+    /// return the file that holds the run's full output, if output is spooled
+    pub fn path(&self, key: &str, run_id: &str, stream: Stream) -> Option<PathBuf> {
+        self.job_folder(key)
+            .map(|folder| folder.join(format!("{}.{}", run_id, stream.extension())))
+    }
+
+    /// return the file the run appends its progress reports to, if output is spooled
+    pub fn progress_path(&self, key: &str, run_id: &str) -> Option<PathBuf> {
+        self.job_folder(key)
+            .map(|folder| folder.join(format!("{}.progress", run_id)))
+    }
+
+    /// create the run's empty progress file in the job's output folder, readable and writable only
+    /// by its owner: the run-as user and group when they are set.  an error if output is not
+    /// spooled, as there is no folder to keep the file in.
+    pub fn create_progress_file(
+        &self,
+        key: &str,
+        run_id: &str,
+        user: Option<u32>,
+        group: Option<u32>,
+    ) -> Result<PathBuf> {
+        let path = self.progress_path(key, run_id).ok_or_else(|| {
+            anyhow!("output is not spooled, so there is no folder for the progress file")
+        })?;
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+
+        // created new, so a file or link left in its place is never written through
+        let _ = std::fs::remove_file(&path);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| anyhow!("could not create {}: {}", path.display(), e))?;
+
+        if user.is_some() || group.is_some() {
+            if let Err(e) = chown(&path, user, group) {
+                let _ = std::fs::remove_file(&path);
+                return Err(e);
+            }
+        }
+
+        Ok(path)
+    }
+
+    /// read the run's full output, skipping `offset` lines and returning up to `limit`
+    pub fn read(
+        &self,
+        key: &str,
+        run_id: &str,
+        stream: Stream,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let path = self
+            .path(key, run_id, stream)
+            .ok_or_else(|| anyhow!("job output is not spooled"))?;
+        let file = File::open(&path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+
+        let mut lines = Vec::new();
+        for line in BufReader::new(file).lines().skip(offset).take(limit) {
+            lines.push(line?);
+        }
+
+        Ok(lines)
+    }
+
+    /// create the output of a new run; when it is dropped, at the end of the run, the job's
+    /// oldest finished runs are deleted
+    pub fn run_output(&self, key: &str, run_id: &str) -> RunOutput {
+        self.active.lock().unwrap().insert(run_id.to_string());
+
+        let tail = |stream| OutputTail {
+            path: self.path(key, run_id, stream),
+            max_lines: self.tail_lines,
+            max_bytes: self.tail_bytes,
+            dropped: 0,
+        };
+
+        RunOutput {
+            log: tail(Stream::Log),
+            errors: tail(Stream::Errors),
+            spool: self.clone(),
+            key: key.to_string(),
+            run_id: run_id.to_string(),
+        }
+    }
+
+    /// delete the spooled output of all but the job's newest `keep` finished runs, by
+    /// modification time; runs in progress are skipped and not counted
+    pub fn prune(&self, key: &str, keep: usize) {
+        let folder = match self.job_folder(key) {
+            Some(folder) => folder,
+            None => return,
+        };
+        let entries = match std::fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        // each run's files by run id, with the time the run last wrote to any of them
+        let mut runs: HashMap<String, (SystemTime, Vec<PathBuf>)> = HashMap::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let run_id = match path.file_stem() {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => continue,
+            };
+            let modified = entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+
+            let run = runs
+                .entry(run_id)
+                .or_insert((SystemTime::UNIX_EPOCH, Vec::new()));
+            run.0 = run.0.max(modified);
+            run.1.push(path);
+        }
+
+        let active = self.active.lock().unwrap().clone();
+        let mut runs: Vec<_> = runs
+            .into_iter()
+            .filter(|(run_id, _)| !active.contains(run_id))
+            .map(|(_, run)| run)
+            .collect();
+        runs.sort_by_key(|(modified, _)| Reverse(*modified));
+        for path in runs.into_iter().skip(keep).flat_map(|(_, paths)| paths) {
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("could not remove old output {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// delete all of the job's spooled output
+    pub fn remove_job(&self, key: &str) {
+        if let Some(folder) = self.job_folder(key) {
+            if let Err(e) = std::fs::remove_dir_all(&folder) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("could not remove output {}: {}", folder.display(), e);
+                }
+            }
+        }
+    }
+
+    /// return the keys of the jobs that have spooled output
+    pub fn jobs(&self) -> Vec<String> {
+        let entries = match self.folder.as_ref().map(std::fs::read_dir) {
+            Some(Ok(entries)) => entries,
+            _ => return Vec::new(),
+        };
+
+        entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect()
+    }
+}
+
+// give the file to the user and group; None leaves that owner as it is
+fn chown(path: &Path, user: Option<u32>, group: Option<u32>) -> Result<()> {
+    let name = CString::new(path.as_os_str().as_bytes())?;
+    let uid = user.unwrap_or(libc::uid_t::MAX);
+    let gid = group.unwrap_or(libc::gid_t::MAX);
+    if unsafe { libc::chown(name.as_ptr(), uid, gid) } != 0 {
+        return Err(anyhow!(
+            "could not give {} to {}:{}: {}",
+            path.display(),
+            uid as i32,
+            gid as i32,
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(())
+}
+
+/// RunOutput - the tails of a run's stdout and stderr, kept across its attempts
+#[derive(Debug)]
+pub struct RunOutput {
+    pub log: OutputTail,
+    pub errors: OutputTail,
+    spool: OutputSpool,
+    key: String,
+    run_id: String,
+}
+
+impl Drop for RunOutput {
+    fn drop(&mut self) {
+        self.spool.active.lock().unwrap().remove(&self.run_id);
+        self.spool.prune(&self.key, self.spool.retention_runs);
+    }
+}
+
+/// OutputTail - keeps the last lines of a stream within the line and byte limits
+#[derive(Debug)]
+pub struct OutputTail {
+    path: Option<PathBuf>,
+    max_lines: usize,
+    max_bytes: usize,
+    dropped: usize, // lines dropped from the front; the first line is the marker when > 0
+}
+
+impl OutputTail {
+    /// open the spool file for appending; None if output is not spooled or it can't be opened,
+    /// in which case the marker no longer points at it
+    pub fn open(&mut self) -> Option<File> {
+        let path = self.path.as_ref()?;
+        let opened = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(path));
+
+        match opened {
+            Ok(file) => Some(file),
+            Err(e) => {
+                warn!("could not spool output to {}: {}", path.display(), e);
+                self.path = None;
+                None
+            }
+        }
+    }
+
+    /// add the line to the tail, dropping the oldest lines over the limits
+    pub fn push(&mut self, lines: &mut Vec<String>, line: String) {
+        if self.dropped > 0 && !lines.is_empty() {
+            lines.remove(0);
+        }
+
+        // the tail may also hold the executor's own messages, so they are counted too
+        lines.push(line);
+        let mut bytes: usize = lines.iter().map(|line| line.len()).sum();
+        while lines.len() > 1 && (lines.len() > self.max_lines || bytes > self.max_bytes) {
+            bytes -= lines.remove(0).len();
+            self.dropped += 1;
+        }
+
+        if self.dropped > 0 {
+            lines.insert(0, self.marker());
+        }
+    }
+
+    fn marker(&self) -> String {
+        match &self.path {
+            Some(path) => format!(
+                "[truncated: {} earlier lines, full output in {}]",
+                self.dropped,
+                path.display()
+            ),
+            None => format!("[truncated: {} earlier lines]", self.dropped),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn tail() {
+        let spool = OutputSpool::new(3, 1024);
+        let mut output = spool.run_output("job-1", "run-1");
+        let mut lines = vec!["attempt 1 of 2".to_string()];
+        for n in 1..=5 {
+            output.log.push(&mut lines, format!("line {}", n));
+        }
+        assert_eq!(
+            lines,
+            vec!["[truncated: 3 earlier lines]", "line 3", "line 4", "line 5"]
+        );
+
+        // the byte limit keeps at least the newest line
+        let spool = OutputSpool::new(100, 10);
+        let mut output = spool.run_output("job-1", "run-1");
+        let mut lines = Vec::new();
+        output.errors.push(&mut lines, "12345".to_string());
+        output.errors.push(&mut lines, "67890".to_string());
+        assert_eq!(lines, vec!["12345", "67890"]);
+        output.errors.push(&mut lines, "a long line".to_string());
+        assert_eq!(lines, vec!["[truncated: 2 earlier lines]", "a long line"]);
+    }
+
+    #[test]
+    fn spool_and_read() {
+        let folder = std::env::temp_dir().join(format!("output-spool-{}", std::process::id()));
+        let spool = OutputSpool::with_folder(&folder, 2, 1024);
+        let mut output = spool.run_output("job-1", "run-1");
+
+        let mut file = output.log.open().unwrap();
+        let mut lines = Vec::new();
+        for n in 1..=4 {
+            writeln!(file, "line {}", n).unwrap();
+            output.log.push(&mut lines, format!("line {}", n));
+        }
+
+        let path = spool.path("job-1", "run-1", Stream::Log).unwrap();
+        assert_eq!(path, folder.join("job-1").join("run-1.log"));
+        assert_eq!(
+            lines[0],
+            format!(
+                "[truncated: 2 earlier lines, full output in {}]",
+                path.display()
+            )
+        );
+        assert_eq!(
+            spool.read("job-1", "run-1", Stream::Log, 0, 10).unwrap(),
+            vec!["line 1", "line 2", "line 3", "line 4"]
+        );
+        assert_eq!(
+            spool.read("job-1", "run-1", Stream::Log, 1, 2).unwrap(),
+            vec!["line 2", "line 3"]
+        );
+        assert!(spool.read("job-1", "run-1", Stream::Errors, 0, 10).is_err());
+        assert!(OutputSpool::default()
+            .read("job-1", "run-1", Stream::Log, 0, 10)
+            .is_err());
+
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn progress_file() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let error = OutputSpool::default()
+            .create_progress_file("job-1", "run-1", None, None)
+            .unwrap_err();
+        assert!(error.to_string().contains("not spooled"));
+
+        let folder = std::env::temp_dir().join(format!("output-progress-{}", std::process::id()));
+        let spool = OutputSpool::with_folder(&folder, 2, 1024);
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let path = spool
+            .create_progress_file("job-1", "run-1", Some(uid), Some(gid))
+            .unwrap();
+        assert_eq!(path, folder.join("job-1").join("run-1.progress"));
+
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        assert_eq!((meta.uid(), meta.gid()), (uid, gid));
+
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn retention() {
+        let folder = std::env::temp_dir().join(format!("output-retention-{}", std::process::id()));
+        let spool = OutputSpool::with_folder(&folder, 2, 1024).with_retention(2);
+
+        let run = |n: usize| {
+            let run_id = format!("run-{}", n);
+            let mut output = spool.run_output("job-1", &run_id);
+            writeln!(output.log.open().unwrap(), "line {}", n).unwrap();
+            writeln!(output.errors.open().unwrap(), "oops {}", n).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            output
+        };
+        for n in 1..=3 {
+            drop(run(n));
+        }
+
+        // finishing the third run dropped the first
+        assert!(spool.read("job-1", "run-1", Stream::Log, 0, 10).is_err());
+        assert!(spool.read("job-1", "run-1", Stream::Errors, 0, 10).is_err());
+        assert_eq!(
+            spool.read("job-1", "run-2", Stream::Log, 0, 10).unwrap(),
+            vec!["line 2"]
+        );
+        assert_eq!(
+            spool.read("job-1", "run-3", Stream::Errors, 0, 10).unwrap(),
+            vec!["oops 3"]
+        );
+
+        // a run in progress is neither deleted nor counted
+        let running = run(4);
+        drop(run(5));
+        drop(run(6));
+        assert!(spool.read("job-1", "run-3", Stream::Log, 0, 10).is_err());
+        assert!(spool.read("job-1", "run-5", Stream::Log, 0, 10).is_ok());
+        assert!(spool.read("job-1", "run-4", Stream::Log, 0, 10).is_ok());
+
+        // once finished it is counted by when it last wrote
+        drop(running);
+        assert!(spool.read("job-1", "run-4", Stream::Log, 0, 10).is_err());
+        assert!(spool.read("job-1", "run-5", Stream::Log, 0, 10).is_ok());
+
+        assert_eq!(spool.jobs(), vec!["job-1"]);
+        spool.remove_job("job-1");
+        assert!(spool.jobs().is_empty());
+        assert!(!folder.join("job-1").exists());
+
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+}
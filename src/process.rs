@@ -0,0 +1,327 @@
+/// Process.  Starts a job's action as an OS process in its own process group with piped output.
+/// the child limits itself between fork and exec: rlimits, then a cgroup of its own for the run,
+/// then the run-as group and user, so nothing the job starts escapes them.  also signals process
+/// groups and reads the pipes a line at a time.
+use crate::models::action::Action;
+use crate::models::limits::ResourceLimits;
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Stdio};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// the cgroup cpu.max period in microseconds
+const CPU_MAX_PERIOD: u64 = 100_000;
+
+/// a line of output from the running process
+#[derive(Debug)]
+pub enum Output {
+    Stdout(String),
+    Stderr(String),
+    Progress(String), // a line from the progress file
+    Closed,           // a pipe reached the end of its output
+}
+
+/// start the action with piped output and the executor's variables added to its environment.
+/// between fork and exec the child limits itself, see `limit_child`, so nothing it starts escapes
+/// the limits.  plain command actions run through the shell, structured actions run the program
+/// with the exact args
+pub fn spawn(
+    action: &Action,
+    limits: &ResourceLimits,
+    cgroup: Option<&RunCgroup>,
+    env: &[(String, String)],
+) -> Result<Child> {
+    let argv = action.argv()?;
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| anyhow!("the action has no program"))?;
+
+    let mut command = std::process::Command::new(program);
+    command
+        .args(args)
+        .envs(env.iter().cloned().chain(action.env()))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(cwd) = action.cwd() {
+        command.current_dir(cwd);
+    }
+
+    let rlimits = rlimits(limits);
+    let procs = cgroup.map(|cgroup| cgroup.procs.clone());
+    let (user, group) = (limits.user, limits.group);
+
+    // safety: the hook runs in the forked child; it only uses data prepared before the fork
+    unsafe {
+        command.pre_exec(move || limit_child(&rlimits, procs.as_ref(), user, group));
+    }
+
+    Ok(command.spawn()?)
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub type Resource = i32;
+
+/// the rlimits to set in the child.  the cpu hard limit is a second above the soft one so the
+/// process gets SIGXCPU first.
+pub fn rlimits(limits: &ResourceLimits) -> Vec<(Resource, libc::rlimit)> {
+    [
+        (libc::RLIMIT_CPU, limits.cpu_seconds, 1),
+        (libc::RLIMIT_AS, limits.address_space_bytes, 0),
+        (libc::RLIMIT_NOFILE, limits.open_files, 0),
+        (libc::RLIMIT_NPROC, limits.processes, 0),
+    ]
+    .iter()
+    .filter_map(|(resource, value, headroom)| {
+        value.map(|value| {
+            let limit = libc::rlimit {
+                rlim_cur: value,
+                rlim_max: value.saturating_add(*headroom),
+            };
+            (*resource, limit)
+        })
+    })
+    .collect()
+}
+
+/// run in the child between fork and exec: start a new process group, set the rlimits, join the
+/// run's cgroup, then switch to the group and user.  only makes async-signal-safe calls and
+/// doesn't allocate.
+fn limit_child(
+    rlimits: &[(Resource, libc::rlimit)],
+    procs: Option<&CString>,
+    user: Option<u32>,
+    group: Option<u32>,
+) -> std::io::Result<()> {
+    let check = |result: i32| match result {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    };
+
+    // safety: plain system calls on values owned by the caller
+    unsafe {
+        check(libc::setpgid(0, 0))?;
+        for (resource, limit) in rlimits {
+            check(libc::setrlimit(*resource, limit))?;
+        }
+
+        if let Some(procs) = procs {
+            // "0" moves the writing process
+            let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+            let error = std::io::Error::last_os_error();
+            libc::close(fd);
+            if written != 1 {
+                return Err(error);
+            }
+        }
+
+        if let Some(group) = group {
+            check(libc::setgid(group))?;
+        }
+        if let Some(user) = user {
+            // drop root's supplementary groups along with root
+            if libc::getuid() == 0 {
+                check(libc::setgroups(0, std::ptr::null()))?;
+            }
+            check(libc::setuid(user))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// RunCgroup - the cgroup v2 group of a single run: a leaf named for the run below the job's
+/// cgroup, with the memory and cpu quotas, so OOM kills are counted for this run alone
+pub struct RunCgroup {
+    path: PathBuf,
+    procs: CString, // the leaf's cgroup.procs, written by the child before it execs
+}
+
+impl RunCgroup {
+    /// create the run's cgroup if the limits name one, enabling the controllers its quotas need
+    /// in the job's cgroup
+    pub fn create(limits: &ResourceLimits, run_id: &str) -> Result<Option<RunCgroup>> {
+        let parent = match limits.cgroup_path()? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let write = |file: PathBuf, value: String| -> Result<()> {
+            std::fs::write(&file, value).map_err(|e| anyhow!("{}: {}", file.display(), e))
+        };
+
+        std::fs::create_dir_all(&parent).map_err(|e| anyhow!("{}: {}", parent.display(), e))?;
+        let mut controllers = Vec::new();
+        if limits.memory_max_bytes.is_some() {
+            controllers.push("+memory");
+        }
+        if limits.cpu_max_percent.is_some() {
+            controllers.push("+cpu");
+        }
+        if !controllers.is_empty() {
+            write(parent.join("cgroup.subtree_control"), controllers.join(" "))?;
+        }
+
+        let path = parent.join(format!("run-{}", run_id));
+        if let Err(e) = std::fs::create_dir(&path) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(anyhow!("{}: {}", path.display(), e));
+            }
+        }
+        let cgroup = RunCgroup {
+            procs: CString::new(path.join("cgroup.procs").as_os_str().as_bytes())?,
+            path,
+        };
+
+        let mut quotas = Ok(());
+        if let Some(bytes) = limits.memory_max_bytes {
+            quotas = quotas.and_then(|_| write(cgroup.path.join("memory.max"), bytes.to_string()));
+        }
+        if let Some(percent) = limits.cpu_max_percent {
+            let quota = (u64::from(percent) * CPU_MAX_PERIOD / 100).max(1000);
+            let value = format!("{} {}", quota, CPU_MAX_PERIOD);
+            quotas = quotas.and_then(|_| write(cgroup.path.join("cpu.max"), value));
+        }
+
+        match quotas {
+            Ok(()) => Ok(Some(cgroup)),
+            Err(e) => {
+                cgroup.remove();
+                Err(e)
+            }
+        }
+    }
+
+    /// the number of OOM kills in the run's cgroup
+    pub fn oom_kills(&self) -> u64 {
+        std::fs::read_to_string(self.path.join("memory.events"))
+            .unwrap_or_default()
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// remove the run's cgroup once its processes are gone
+    pub fn remove(self) {
+        if let Err(e) = std::fs::remove_dir(&self.path) {
+            warn!("could not remove cgroup {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// send SIGTERM to the process group and return a task that sends SIGKILL after the grace period
+pub fn stop_group(pid: Option<u64>, grace: u64) -> JoinHandle<()> {
+    signal_group(pid, libc::SIGTERM);
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(grace)).await;
+        warn!("process group {:?} still running, sending SIGKILL", pid);
+        signal_group(pid, libc::SIGKILL);
+    })
+}
+
+/// send the signal to every process in the group led by pid; jobs are started with setpgid
+pub fn signal_group(pid: Option<u64>, signal: i32) {
+    let pgid = match pid.and_then(|pid| libc::pid_t::try_from(pid).ok()) {
+        Some(pgid) if pgid > 0 => pgid,
+        _ => return,
+    };
+
+    // safety: killpg only sends a signal, an unknown group returns ESRCH
+    if unsafe { libc::killpg(pgid, signal) } != 0 {
+        warn!(
+            "could not signal process group {}: {}",
+            pgid,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// read lines from the pipe on a thread, writing each one to the spool file and sending it to the
+/// channel.  bytes that are not utf-8 are replaced, and the pipe is drained to the end even if the
+/// channel closes so the process never dies of SIGPIPE.
+pub fn read_lines<R: Read + Send + 'static>(
+    pipe: R,
+    spool: Option<File>,
+    tx: mpsc::UnboundedSender<Output>,
+    wrap: fn(String) -> Output,
+) {
+    std::thread::spawn(move || {
+        let mut spool = spool.map(LineWriter::new);
+        let mut reader = BufReader::new(pipe);
+        let mut sending = true;
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if let Some(file) = spool.as_mut() {
+                        let written = file.write_all(&buf).and_then(|_| {
+                            if buf.ends_with(b"\n") {
+                                Ok(())
+                            } else {
+                                file.write_all(b"\n")
+                            }
+                        });
+                        if let Err(e) = written {
+                            warn!("stopped spooling job output: {}", e);
+                            spool = None;
+                        }
+                    }
+
+                    if sending {
+                        let line = String::from_utf8_lossy(&buf);
+                        let line = line.trim_end_matches('\n').trim_end_matches('\r');
+                        sending = tx.send(wrap(line.to_string())).is_ok();
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("stopped reading job output: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let _ = tx.send(Output::Closed);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let limits = ResourceLimits {
+            cpu_seconds: Some(10),
+            open_files: Some(32),
+            ..Default::default()
+        };
+        let set = rlimits(&limits);
+        assert_eq!(set.len(), 2);
+        assert_eq!(set[0].0, libc::RLIMIT_CPU);
+        assert_eq!((set[0].1.rlim_cur, set[0].1.rlim_max), (10, 11));
+        assert_eq!(set[1].0, libc::RLIMIT_NOFILE);
+        assert_eq!((set[1].1.rlim_cur, set[1].1.rlim_max), (32, 32));
+
+        assert!(rlimits(&ResourceLimits::default()).is_empty());
+        assert!(RunCgroup::create(&ResourceLimits::default(), "run-1")
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,105 @@
+/// RunReports.  Collects what a running job reports besides its output: progress lines the job
+/// appends to its progress file, and the results document from the job's `results_file` or its
+/// last marked stdout line.
+use crate::models::jobs::Job;
+use crate::models::results::Results;
+use crate::process::Output;
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// how often the progress file is checked for new reports
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// the job's results file, relative to the action's cwd
+pub fn results_path(job: &Job) -> Option<PathBuf> {
+    let file = job.results_file.as_ref()?;
+    match job.action.cwd() {
+        Some(cwd) => Some(Path::new(cwd).join(file)),
+        None => Some(PathBuf::from(file)),
+    }
+}
+
+/// parse the run's results from the results file, if the run wrote it, else the marked stdout
+/// line; None if the job reported no results
+pub fn read_results(file: Option<&Path>, marked: Option<String>) -> Result<Option<Results>> {
+    if let Some(path) = file.filter(|path| path.exists()) {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("could not read results {}: {}", path.display(), e))?;
+        return Results::parse(&text).map(Some);
+    }
+
+    marked.map(|json| Results::parse(&json)).transpose()
+}
+
+/// poll the progress file on a thread while `watching`, sending each new complete line to the
+/// channel; the job appends its reports, the file may not exist until the first one
+pub fn watch_progress(path: PathBuf, tx: mpsc::UnboundedSender<Output>, watching: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        let mut reader: Option<BufReader<File>> = None;
+        let mut line = String::new();
+        while watching.load(Ordering::SeqCst) {
+            if reader.is_none() {
+                reader = File::open(&path).ok().map(BufReader::new);
+            }
+
+            if let Some(reader) = reader.as_mut() {
+                // a partial line stays in `line` until the rest is written
+                while let Ok(count) = reader.read_line(&mut line) {
+                    if count == 0 || !line.ends_with('\n') {
+                        break;
+                    }
+                    if tx
+                        .send(Output::Progress(line.trim_end().to_string()))
+                        .is_err()
+                    {
+                        return;
+                    }
+                    line.clear();
+                }
+            }
+
+            std::thread::sleep(PROGRESS_POLL_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results() {
+        let marked = Some(r#"{"rows": 1}"#.to_string());
+        assert_eq!(
+            read_results(None, marked.clone()).unwrap().unwrap()["rows"],
+            1
+        );
+        assert_eq!(read_results(None, None).unwrap(), None);
+
+        // the file wins over the marked line, a missing file doesn't count
+        let file = std::env::temp_dir().join(format!("run-reports-{}.json", std::process::id()));
+        assert_eq!(
+            read_results(Some(&file), marked.clone()).unwrap().unwrap()["rows"],
+            1
+        );
+        std::fs::write(&file, r#"{"rows": 7}"#).unwrap();
+        assert_eq!(
+            read_results(Some(&file), marked).unwrap().unwrap()["rows"],
+            7
+        );
+        std::fs::write(&file, "{bad").unwrap();
+        assert!(read_results(Some(&file), None).is_err());
+        let _ = std::fs::remove_file(&file);
+
+        let mut job = Job::new("backup", "true");
+        assert_eq!(results_path(&job), None);
+        job.results_file = Some("results.json".to_string());
+        assert_eq!(results_path(&job), Some(PathBuf::from("results.json")));
+    }
+}
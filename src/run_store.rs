@@ -0,0 +1,168 @@
+/// RunStore.  Keeps the run history of each job, newest first, implemented with messaging like
+/// the JobStore; only the most recent `limit` runs of each job are kept.
+use crate::models::runs::JobRun;
+use hashbrown::HashMap;
+use log::info;
+use std::collections::VecDeque;
+use std::vec::Vec;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+
+/// the number of runs kept for each job when no limit is configured
+pub const DEFAULT_RUN_HISTORY_LIMIT: usize = 100;
+
+#[derive(Debug)]
+pub enum Command {
+    Insert(Box<JobRun>, oneshot::Sender<JobRun>), // insert or replace the run with the same id
+    Find(String, oneshot::Sender<Option<JobRun>>),
+    List(String, usize, usize, oneshot::Sender<Vec<JobRun>>), // job key, offset, limit; newest first
+}
+
+#[derive(Debug)]
+pub struct RunStore {
+    req_sender: mpsc::Sender<Command>,
+}
+
+impl RunStore {
+    /// create the store, keeping the default number of runs per job
+    pub async fn new() -> RunStore {
+        RunStore::with_limit(DEFAULT_RUN_HISTORY_LIMIT).await
+    }
+
+    /// create the store, keeping up to `limit` runs per job
+    pub async fn with_limit(limit: usize) -> RunStore {
+        let (req_sender, mut req_receiver) = mpsc::channel::<Command>(64);
+        let limit = limit.max(1);
+
+        tokio::spawn(async move {
+            // job key -> runs, newest at the front; run id -> job key
+            let mut runs: HashMap<String, VecDeque<JobRun>> = HashMap::new();
+            let mut keys: HashMap<String, String> = HashMap::new();
+
+            while let Some(cmd) = req_receiver.recv().await {
+                info!("run req recv: {:?}", cmd);
+                match cmd {
+                    Command::Insert(run, tx) => {
+                        let run = *run;
+                        let list = runs.entry(run.job_key.to_string()).or_default();
+                        match list.iter_mut().find(|r| r.id == run.id) {
+                            Some(existing) => *existing = run.clone(),
+                            None => {
+                                keys.insert(run.id.to_string(), run.job_key.to_string());
+                                list.push_front(run.clone());
+                                while list.len() > limit {
+                                    if let Some(old) = list.pop_back() {
+                                        keys.remove(&old.id);
+                                    }
+                                }
+                            }
+                        }
+
+                        let _ = tx.send(run);
+                    }
+                    Command::Find(id, tx) => {
+                        let run = keys
+                            .get(&id)
+                            .and_then(|key| runs.get(key))
+                            .and_then(|list| list.iter().find(|r| r.id == id))
+                            .cloned();
+
+                        let _ = tx.send(run);
+                    }
+                    Command::List(key, offset, limit, tx) => {
+                        let list = match runs.get(&key) {
+                            Some(list) => list.iter().skip(offset).take(limit).cloned().collect(),
+                            None => Vec::new(),
+                        };
+
+                        let _ = tx.send(list);
+                    }
+                }
+            }
+
+            req_receiver.close();
+        });
+
+        RunStore { req_sender }
+    }
+
+    /// clients get access to the request channel to send commands
+    pub fn request_channel(&self) -> mpsc::Sender<Command> {
+        self.req_sender.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert(channel: &mpsc::Sender<Command>, run: &JobRun) {
+        let (tx, rx) = oneshot::channel();
+        channel
+            .send(Command::Insert(Box::new(run.clone()), tx))
+            .await
+            .unwrap();
+        assert_eq!(&rx.await.unwrap(), run);
+    }
+
+    async fn list(
+        channel: &mpsc::Sender<Command>,
+        key: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<JobRun> {
+        let (tx, rx) = oneshot::channel();
+        channel
+            .send(Command::List(key.to_string(), offset, limit, tx))
+            .await
+            .unwrap();
+        rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn history() {
+        let store = RunStore::with_limit(3).await;
+        let channel = store.request_channel();
+
+        let runs: Vec<JobRun> = (0..4).map(|_| JobRun::new("job-1")).collect();
+        for run in runs.iter() {
+            insert(&channel, run).await;
+        }
+        insert(&channel, &JobRun::new("job-2")).await;
+
+        // newest first, the oldest was dropped
+        let ids: Vec<String> = list(&channel, "job-1", 0, 10)
+            .await
+            .into_iter()
+            .map(|run| run.id)
+            .collect();
+        assert_eq!(
+            ids,
+            vec![runs[3].id.clone(), runs[2].id.clone(), runs[1].id.clone()]
+        );
+
+        let page = list(&channel, "job-1", 1, 1).await;
+        assert_eq!(page, vec![runs[2].clone()]);
+        assert!(list(&channel, "job-3", 0, 10).await.is_empty());
+
+        // update in place
+        let mut run = runs[3].clone();
+        run.log.push("done".to_string());
+        insert(&channel, &run).await;
+        assert_eq!(list(&channel, "job-1", 0, 10).await.len(), 3);
+
+        let (tx, rx) = oneshot::channel();
+        channel
+            .send(Command::Find(run.id.to_string(), tx))
+            .await
+            .unwrap();
+        assert_eq!(rx.await.unwrap(), Some(run));
+
+        let (tx, rx) = oneshot::channel();
+        channel
+            .send(Command::Find(runs[0].id.to_string(), tx))
+            .await
+            .unwrap();
+        assert_eq!(rx.await.unwrap(), None);
+    }
+}
@@ -0,0 +1,683 @@
+/// Scheduler.  Keeps a time ordered queue with the next fire time of every scheduled job in the
+/// JobStore, sleeps until the earliest one, fires it and computes the job's next time.
+///
+/// the queue is kept current from the store's broadcast events (insert, update and remove), so
+/// the store is only rescanned at startup or if the event channel lags.  a job is only rescheduled
+/// when its schedule, calendar or deleted status change, not on each update of a run's output.  jobs
+/// with dependencies are never queued, they are fired by the WorkflowRunner when their upstream
+/// jobs succeed.
+///
+/// calendars come from the CalendarStore; when one is inserted or removed the queue is rebuilt
+/// with the new calendars, and a due job is checked against its calendar again before it fires.
+///
+/// fire times are recorded in the FireLog; at startup, jobs with a misfire policy catch up the
+/// runs they missed since their last recorded fire.
+use crate::calendar_store::{self, CalendarStore};
+use crate::fire_log::FireLog;
+use crate::job_store::{Command, JobStore};
+use crate::models::calendar::Calendar;
+use crate::models::jobs::{EventKind, Job, JobEvent};
+use crate::models::schedule::Schedule;
+use chrono::{DateTime, Utc};
+use domain_keys::models::{Model, Status};
+use hashbrown::HashMap;
+use log::{debug, error, info, warn};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// the longest the loop sleeps when nothing is scheduled; events wake it sooner
+const IDLE_SLEEP_SECONDS: u64 = 3600;
+
+/// Scheduler - the fire time queue.  Entries in the heap are only valid while they match the
+/// fire time in `jobs`; stale entries left by updates and removals are skipped when popped.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<(DateTime<Utc>, String)>>,
+    jobs: HashMap<String, (DateTime<Utc>, Model<Job>)>,
+    timings: HashMap<String, Timing>, // what each job was last scheduled from
+    calendars: HashMap<String, Calendar>,
+    fire_log: FireLog,
+}
+
+/// Input - what the scheduler loop handles besides due jobs: job changes, a reload after missed
+/// changes, and calendar changes
+#[derive(Debug)]
+enum Input {
+    Event(Box<JobEvent>),
+    Reload,
+    Calendars,
+}
+
+/// Timing - the parts of a job that decide when it fires
+#[derive(Debug, PartialEq)]
+struct Timing {
+    run_at: Option<Schedule>,
+    calendar: Option<String>,
+    deleted: bool,
+    dependent: bool,
+}
+
+impl Timing {
+    fn of(model: &Model<Job>) -> Timing {
+        Timing {
+            run_at: model.value.run_at.clone(),
+            calendar: model.value.calendar.clone(),
+            deleted: matches!(model.status, Status::Deleted(_)),
+            dependent: !model.value.depends_on.is_empty(),
+        }
+    }
+}
+
+impl Scheduler {
+    /// create an empty scheduler
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    /// create an empty scheduler that records fire times in the log
+    pub fn with_fire_log(fire_log: FireLog) -> Scheduler {
+        Scheduler {
+            fire_log,
+            ..Default::default()
+        }
+    }
+
+    /// replace the calendars used to skip excluded dates
+    pub fn set_calendars(&mut self, calendars: Vec<Calendar>) {
+        self.calendars = calendars
+            .into_iter()
+            .map(|cal| (cal.name.to_string(), cal))
+            .collect();
+    }
+
+    /// compute the job's next fire time after `after` and queue it, replacing any earlier entry;
+    /// returns the fire time or None if the job has nothing left to run.
+    pub fn schedule(&mut self, model: Model<Job>, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let key = model.key.to_string();
+        self.jobs.remove(&key);
+        self.timings.insert(key.to_string(), Timing::of(&model));
+
+        if let Status::Deleted(_) = model.status {
+            return None;
+        }
+
+        if !model.value.depends_on.is_empty() {
+            debug!("job {} runs after its dependencies, not scheduled", key);
+            return None;
+        }
+
+        let at = model.value.next_fire(after, self.calendar_for(&model))?;
+        debug!("schedule job {} at {}", key, at);
+
+        self.queue.push(Reverse((at, key.to_string())));
+        self.jobs.insert(key, (at, model));
+
+        Some(at)
+    }
+
+    /// replace the queued copy of the job if its fire time can't have changed since it was
+    /// scheduled; returns false if the job is new or its timing changed, so it must be scheduled.
+    pub fn refresh(&mut self, model: &Model<Job>) -> bool {
+        if self.timings.get(&model.key) != Some(&Timing::of(model)) {
+            return false;
+        }
+
+        if let Some((_, queued)) = self.jobs.get_mut(&model.key) {
+            *queued = model.clone();
+        }
+
+        true
+    }
+
+    /// remove the job from the queue and forget its fire time
+    pub fn remove(&mut self, key: &str) -> Option<Model<Job>> {
+        self.fire_log.remove(key);
+        self.timings.remove(key);
+        self.jobs.remove(key).map(|(_, model)| model)
+    }
+
+    /// return the runs the job missed since it last fired that its misfire policy says to run now,
+    /// oldest first; the most recent is recorded as the job's last fire.  a job that never fired
+    /// counts from the start of its schedule, so a once or delay job that came due while the
+    /// service was down still runs.
+    pub fn catch_up(&mut self, model: &Model<Job>, now: &DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let last_fired = self.fire_log.last_fired(&model.key).or_else(|| {
+            let schedule = model.value.run_at.as_ref()?;
+            schedule.start()
+        });
+        let last_fired = match last_fired {
+            Some(last_fired) => last_fired,
+            None => return Vec::new(),
+        };
+
+        let missed = model
+            .value
+            .missed_fires(&last_fired, now, self.calendar_for(model));
+        if let Some(last) = missed.last() {
+            info!(
+                "job {} missed runs since {}, catching up {}",
+                model.key,
+                last_fired,
+                missed.len()
+            );
+            self.fire_log.record(&model.key, last);
+        }
+
+        missed
+    }
+
+    // true if the job's calendar excludes the date of the fire time
+    fn is_excluded(&self, model: &Model<Job>, at: &DateTime<Utc>) -> bool {
+        let date = model
+            .value
+            .run_at
+            .as_ref()
+            .and_then(|schedule| schedule.local_date(at));
+
+        match (self.calendar_for(model), date) {
+            (Some(calendar), Some(date)) => calendar.excludes(&date),
+            _ => false,
+        }
+    }
+
+    fn calendar_for(&self, model: &Model<Job>) -> Option<&Calendar> {
+        let name = model.value.calendar.as_ref()?;
+        let calendar = self.calendars.get(name);
+        if calendar.is_none() {
+            warn!("job {} references unknown calendar: {}", model.key, name);
+        }
+
+        calendar
+    }
+
+    /// the number of jobs waiting to fire
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// return true if no jobs are waiting to fire
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// return the earliest fire time in the queue
+    pub fn next_due(&mut self) -> Option<DateTime<Utc>> {
+        while let Some(Reverse((at, key))) = self.queue.peek() {
+            match self.jobs.get(key) {
+                Some((job_at, _)) if job_at == at => return Some(*at),
+                _ => {
+                    // stale entry
+                    self.queue.pop();
+                }
+            }
+        }
+
+        None
+    }
+
+    /// remove and return every job due at or before `now`, queueing each job's next run after now
+    pub fn pop_due(&mut self, now: &DateTime<Utc>) -> Vec<(DateTime<Utc>, Model<Job>)> {
+        let mut due = Vec::new();
+
+        while let Some(at) = self.next_due() {
+            if at > *now {
+                break;
+            }
+
+            // next_due leaves a valid entry on top of the heap
+            if let Some(Reverse((at, key))) = self.queue.pop() {
+                if let Some((_, model)) = self.jobs.remove(&key) {
+                    // the calendar may have changed since the fire time was computed
+                    if self.is_excluded(&model, &at) {
+                        info!("job {} skipped, {} is excluded by its calendar", key, at);
+                    } else {
+                        self.fire_log.record(&key, &at);
+                        due.push((at, model.clone()));
+                    }
+                    self.schedule(model, now);
+                }
+            }
+        }
+
+        due
+    }
+
+    /// start the scheduler loop: load the jobs in the store and catch up missed runs, then fire
+    /// them on time, sending each fired job to `fire_tx`.  the jobs are rescheduled when the
+    /// calendar store's calendars change.
+    pub fn start(
+        store: &JobStore,
+        calendars: &CalendarStore,
+        fire_log: FireLog,
+        fire_tx: mpsc::Sender<Model<Job>>,
+    ) -> JoinHandle<()> {
+        // subscribe before the initial scan so no changes are missed
+        let events = store.subscribe();
+        let changes = calendars.subscribe();
+        let request_channel = store.request_channel();
+        let calendar_channel = calendars.request_channel();
+        let (input_tx, mut inputs) = mpsc::channel(64);
+        forward_events(events, input_tx.clone());
+        forward_calendars(changes, input_tx);
+
+        tokio::spawn(async move {
+            let mut scheduler = Scheduler::with_fire_log(fire_log);
+            scheduler.set_calendars(list_calendars(&calendar_channel).await);
+            let missed = scheduler.load(&request_channel, true).await;
+            if !fire(&fire_tx, missed).await {
+                return;
+            }
+
+            loop {
+                let sleep = match scheduler.next_due() {
+                    Some(at) => (at - Utc::now()).to_std().unwrap_or_default(),
+                    None => std::time::Duration::from_secs(IDLE_SLEEP_SECONDS),
+                };
+
+                // wait for the next job to come due or for a change to the jobs or calendars
+                let input = match tokio::time::timeout(sleep, inputs.recv()).await {
+                    Ok(Some(input)) => input,
+                    Ok(None) => {
+                        info!("job store closed, stopping the scheduler");
+                        return;
+                    }
+                    Err(_) => {
+                        if !fire(&fire_tx, scheduler.pop_due(&Utc::now())).await {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
+                let event = match input {
+                    Input::Event(event) => *event,
+                    Input::Reload => {
+                        scheduler.load(&request_channel, false).await;
+                        continue;
+                    }
+                    Input::Calendars => {
+                        // fire what is due under the old calendars before the queue is rebuilt
+                        if !fire(&fire_tx, scheduler.pop_due(&Utc::now())).await {
+                            return;
+                        }
+                        scheduler.set_calendars(list_calendars(&calendar_channel).await);
+                        scheduler.load(&request_channel, false).await;
+                        continue;
+                    }
+                };
+
+                let model = match event.model {
+                    Some(model) => model,
+                    None => continue,
+                };
+
+                match event.kind {
+                    EventKind::Inserted | EventKind::Updated if !scheduler.refresh(&model) => {
+                        scheduler.schedule(model, &Utc::now());
+                    }
+                    EventKind::Removed => {
+                        scheduler.remove(&model.key);
+                    }
+                    _ => (),
+                }
+            }
+        })
+    }
+
+    /// rebuild the queue from every job in the store; with catch_up return the missed runs
+    async fn load(
+        &mut self,
+        request_channel: &mpsc::Sender<Command>,
+        catch_up: bool,
+    ) -> Vec<(DateTime<Utc>, Model<Job>)> {
+        let mut missed = Vec::new();
+        let (tx, rx) = oneshot::channel();
+        if request_channel
+            .send(Command::List(0, usize::MAX, tx))
+            .await
+            .is_err()
+        {
+            error!("could not list jobs from the store");
+            return missed;
+        }
+
+        let now = Utc::now();
+        self.queue.clear();
+        self.jobs.clear();
+        self.timings.clear();
+        for model in rx.await.unwrap_or_default() {
+            if catch_up && !matches!(model.status, Status::Deleted(_)) {
+                for at in self.catch_up(&model, &now) {
+                    missed.push((at, model.clone()));
+                }
+            }
+            self.schedule(model, &now);
+        }
+
+        info!("scheduler loaded {} jobs", self.len());
+
+        missed
+    }
+}
+
+/// send the fired jobs to the channel; false if the channel is closed
+async fn fire(fire_tx: &mpsc::Sender<Model<Job>>, fired: Vec<(DateTime<Utc>, Model<Job>)>) -> bool {
+    for (at, model) in fired {
+        info!("fire job {} scheduled for {}", model.key, at);
+        if fire_tx.send(model).await.is_err() {
+            error!("fire channel closed, stopping the scheduler");
+            return false;
+        }
+    }
+
+    true
+}
+
+/// pass the store's job events to the scheduler loop, asking for a reload when events were missed
+fn forward_events(mut events: broadcast::Receiver<JobEvent>, inputs: mpsc::Sender<Input>) {
+    tokio::spawn(async move {
+        loop {
+            let input = match events.recv().await {
+                Ok(event) => Input::Event(Box::new(event)),
+                Err(RecvError::Lagged(count)) => {
+                    warn!("scheduler missed {} job events, reloading", count);
+                    Input::Reload
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            if inputs.send(input).await.is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// tell the scheduler loop that the calendars changed; a missed change is still a change
+fn forward_calendars(mut changes: broadcast::Receiver<String>, inputs: mpsc::Sender<Input>) {
+    tokio::spawn(async move {
+        loop {
+            match changes.recv().await {
+                Ok(name) => info!("calendar {} changed, rescheduling", name),
+                Err(RecvError::Lagged(_)) => info!("calendars changed, rescheduling"),
+                Err(RecvError::Closed) => return,
+            }
+
+            if inputs.send(Input::Calendars).await.is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// request the current calendars; an empty list if the calendar store is not running
+async fn list_calendars(channel: &mpsc::Sender<calendar_store::Command>) -> Vec<Calendar> {
+    let (tx, rx) = oneshot::channel();
+    if channel
+        .send(calendar_store::Command::List(tx))
+        .await
+        .is_err()
+    {
+        return Vec::new();
+    }
+
+    rx.await.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar_store::CalendarStore;
+    use crate::models::jobs::MisfirePolicy;
+    use crate::models::run_at::RunAt;
+    use crate::models::schedule::Schedule;
+    use chrono::Duration;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn every_minute() -> Model<Job> {
+        let mut run_at = RunAt::from_cron("* * * * *").unwrap();
+        run_at.timezone = Some("UTC".to_string());
+        Job::create_model(&Job::with_run_at("minutes", "no-op", run_at))
+    }
+
+    #[test]
+    fn schedule_and_pop() {
+        let mut scheduler = Scheduler::new();
+        let start = utc("2022-12-25T08:00:30Z");
+
+        let once = Job::create_model(&Job::with_schedule(
+            "once",
+            "no-op",
+            Schedule::once(utc("2022-12-25T08:00:45Z")),
+        ));
+        let minutes = every_minute();
+        let unscheduled = Job::create_model(&Job::new("no schedule", "no-op"));
+
+        assert_eq!(
+            scheduler.schedule(once.clone(), &start),
+            Some(utc("2022-12-25T08:00:45Z"))
+        );
+        assert_eq!(
+            scheduler.schedule(minutes.clone(), &start),
+            Some(utc("2022-12-25T08:01:00Z"))
+        );
+        assert_eq!(scheduler.schedule(unscheduled, &start), None);
+        assert_eq!(scheduler.len(), 2);
+
+        assert_eq!(scheduler.next_due(), Some(utc("2022-12-25T08:00:45Z")));
+        assert!(scheduler.pop_due(&start).is_empty());
+
+        let due = scheduler.pop_due(&utc("2022-12-25T08:00:50Z"));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1.key, once.key);
+
+        // the one shot job is gone, the recurring job is queued again after now
+        let due = scheduler.pop_due(&utc("2022-12-25T08:01:00Z"));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, utc("2022-12-25T08:01:00Z"));
+        assert_eq!(scheduler.next_due(), Some(utc("2022-12-25T08:02:00Z")));
+        assert_eq!(scheduler.len(), 1);
+
+        // a long pause fires once, not once per missed minute
+        let due = scheduler.pop_due(&utc("2022-12-25T09:00:30Z"));
+        assert_eq!(due.len(), 1);
+        assert_eq!(scheduler.next_due(), Some(utc("2022-12-25T09:01:00Z")));
+
+        assert!(scheduler.remove(&minutes.key).is_some());
+        assert_eq!(scheduler.next_due(), None);
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn reschedule_replaces_entry() {
+        let mut scheduler = Scheduler::new();
+        let start = utc("2022-12-25T08:00:30Z");
+        let mut model = every_minute();
+
+        scheduler.schedule(model.clone(), &start);
+        model.value.run_at = Some(Schedule::once(utc("2022-12-25T10:00:00Z")));
+        scheduler.schedule(model, &start);
+
+        assert_eq!(scheduler.len(), 1);
+        assert_eq!(scheduler.next_due(), Some(utc("2022-12-25T10:00:00Z")));
+        assert!(scheduler.pop_due(&utc("2022-12-25T09:00:00Z")).is_empty());
+    }
+
+    #[test]
+    fn refresh_keeps_fire_time() {
+        let mut scheduler = Scheduler::new();
+        let start = utc("2022-12-25T08:00:30Z");
+        let mut model = every_minute();
+        assert!(!scheduler.refresh(&model));
+        scheduler.schedule(model.clone(), &start);
+
+        // a run's output doesn't move the fire time, the queued copy is replaced
+        model.value.log.push("line 1".to_string());
+        let model = Job::update_model(&model, Status::Active(0));
+        assert!(scheduler.refresh(&model));
+        let due = scheduler.pop_due(&utc("2022-12-25T08:01:00Z"));
+        assert_eq!(due[0].1.value.log, vec!["line 1"]);
+
+        let mut changed = model.clone();
+        changed.value.calendar = Some("freeze".to_string());
+        assert!(!scheduler.refresh(&changed));
+        let deleted = Job::update_model(&model, Status::Deleted(0));
+        assert!(!scheduler.refresh(&deleted));
+
+        // jobs without a schedule are remembered too
+        let unscheduled = Job::create_model(&Job::new("no schedule", "no-op"));
+        assert_eq!(scheduler.schedule(unscheduled.clone(), &start), None);
+        assert!(scheduler.refresh(&unscheduled));
+
+        // a job with dependencies is left to them, even with a schedule
+        let mut dependent = every_minute();
+        dependent.value.depends_on = vec!["upstream".to_string()];
+        assert!(!scheduler.refresh(&dependent));
+        assert_eq!(scheduler.schedule(dependent, &start), None);
+    }
+
+    #[test]
+    fn calendars() {
+        let mut scheduler = Scheduler::new();
+        let mut calendar = Calendar::new("freeze");
+        calendar.exclude_date(chrono::NaiveDate::from_ymd_opt(2022, 12, 25).unwrap(), "");
+        scheduler.set_calendars(vec![calendar]);
+
+        let mut model = every_minute();
+        model.value.calendar = Some("freeze".to_string());
+
+        assert_eq!(
+            scheduler.schedule(model.clone(), &utc("2022-12-25T08:00:30Z")),
+            Some(utc("2022-12-26T00:00:00Z"))
+        );
+
+        // a date excluded after the job was queued is skipped when it comes due
+        let mut calendar = Calendar::new("freeze");
+        calendar.exclude_date(chrono::NaiveDate::from_ymd_opt(2022, 12, 26).unwrap(), "");
+        scheduler.set_calendars(vec![calendar]);
+        assert!(scheduler.pop_due(&utc("2022-12-26T00:00:30Z")).is_empty());
+        assert_eq!(scheduler.next_due(), Some(utc("2022-12-27T00:00:00Z")));
+    }
+
+    #[test]
+    fn catch_up() {
+        let mut scheduler = Scheduler::new();
+        let mut model = every_minute();
+        model.value.misfire = MisfirePolicy::RunAll(2);
+
+        // a cron job that never fired has nothing to catch up
+        let now = utc("2022-12-25T08:00:30Z");
+        assert!(scheduler.catch_up(&model, &now).is_empty());
+
+        scheduler.schedule(model.clone(), &utc("2022-12-25T07:55:30Z"));
+        assert_eq!(scheduler.pop_due(&now).len(), 1);
+        assert_eq!(
+            scheduler.fire_log.last_fired(&model.key),
+            Some(utc("2022-12-25T07:56:00Z"))
+        );
+
+        let missed = scheduler.catch_up(&model, &now);
+        assert_eq!(
+            missed,
+            vec![utc("2022-12-25T07:59:00Z"), utc("2022-12-25T08:00:00Z")]
+        );
+        assert!(scheduler.catch_up(&model, &now).is_empty());
+
+        scheduler.remove(&model.key);
+        assert_eq!(scheduler.fire_log.last_fired(&model.key), None);
+
+        // a once job that never fired catches up its time
+        let at = utc("2022-12-25T07:00:00Z");
+        let mut job = Job::with_schedule("once", "no-op", Schedule::once(at));
+        job.misfire = MisfirePolicy::RunOnce;
+        let model = Job::create_model(&job);
+        assert_eq!(scheduler.catch_up(&model, &now), vec![at]);
+        assert!(scheduler.catch_up(&model, &now).is_empty());
+    }
+
+    #[tokio::test]
+    async fn fires_inserted_jobs() {
+        let store = JobStore::new().await;
+        let folder = std::env::temp_dir().join(format!("sched-cal-{}", std::process::id()));
+        let calendars = CalendarStore::with_folder(&folder).await;
+        let (fire_tx, mut fire_rx) = mpsc::channel(8);
+
+        let handle = Scheduler::start(&store, &calendars, FireLog::new(), fire_tx);
+
+        let at = Utc::now() + Duration::milliseconds(200);
+        let model = Job::create_model(&Job::with_schedule("soon", "no-op", Schedule::once(at)));
+        let (tx, _rx) = oneshot::channel();
+        store
+            .request_channel()
+            .send(Command::Insert(Box::new(model.clone()), tx))
+            .await
+            .unwrap();
+
+        let fired = tokio::time::timeout(std::time::Duration::from_secs(5), fire_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(fired.key, model.key);
+        assert!(Utc::now() >= at);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn calendar_changes() {
+        let store = JobStore::new().await;
+        let folder = std::env::temp_dir().join(format!("sched-changes-{}", std::process::id()));
+        let calendars = CalendarStore::with_folder(&folder).await;
+        let calendar_channel = calendars.request_channel();
+        let (fire_tx, mut fire_rx) = mpsc::channel(8);
+
+        // today and tomorrow are frozen, so the job isn't due for days
+        let today = Utc::now().date_naive();
+        let mut freeze = Calendar::new("freeze");
+        freeze.exclude_range(today, today.succ_opt().unwrap(), "release");
+        let (tx, rx) = oneshot::channel();
+        let cmd = calendar_store::Command::Insert(Box::new(freeze), tx);
+        calendar_channel.send(cmd).await.unwrap();
+        rx.await.unwrap().unwrap();
+
+        let schedule = Schedule::Interval {
+            every_seconds: 1,
+            start: Utc::now(),
+        };
+        let mut job = Job::with_schedule("frozen", "no-op", schedule);
+        job.calendar = Some("freeze".to_string());
+        let model = Job::create_model(&job);
+        let (tx, rx) = oneshot::channel();
+        store
+            .request_channel()
+            .send(Command::Insert(Box::new(model.clone()), tx))
+            .await
+            .unwrap();
+        rx.await.unwrap().unwrap();
+
+        let handle = Scheduler::start(&store, &calendars, FireLog::new(), fire_tx);
+
+        // lifting the freeze reschedules the queued job
+        let (tx, rx) = oneshot::channel();
+        let cmd = calendar_store::Command::Insert(Box::new(Calendar::new("freeze")), tx);
+        calendar_channel.send(cmd).await.unwrap();
+        rx.await.unwrap().unwrap();
+
+        let fired = tokio::time::timeout(std::time::Duration::from_secs(10), fire_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(fired.key, model.key);
+
+        handle.abort();
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+}
@@ -0,0 +1,468 @@
+/// WorkflowRunner.  Runs dependent jobs: when a job's run completes successfully, each job that
+/// depends on it is fired once all of its dependencies have succeeded since it last ran (fan-in);
+/// when a run fails, every job downstream of it is marked Blocked.  a job with dependencies is run
+/// only by the runner, so it can't also have a `run_at` schedule, and a dependent that is Blocked
+/// for any reason other than a failed upstream job is not fired.  the JobStore rejects inserts and
+/// updates that break the dependency rules; a job that reaches the runner without that check, such
+/// as one loaded with `JobStore::with_list`, is kept without its dependencies and set Blocked(2).
+///
+/// job definitions come from the JobStore's insert, update and remove events, reloaded from the
+/// store if the events lag.  run results come from the executor's completions channel rather than
+/// the broadcast, so a completed run is never dropped.
+use crate::job_store::{Command, JobStore};
+use crate::models::jobs::{EventKind, Job, JobEvent};
+use crate::models::workflow::Workflow;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use domain_keys::models::{Model, Status};
+use hashbrown::HashMap;
+use log::{error, info, warn};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// Blocked value for a job whose upstream job failed
+pub const BLOCKED_UPSTREAM_FAILED: u8 = 1;
+/// Blocked value for a job whose dependencies form a cycle, or that has both dependencies and a
+/// `run_at` schedule
+pub const BLOCKED_INVALID_DEPENDENCIES: u8 = 2;
+
+#[derive(Debug, Default)]
+pub struct WorkflowRunner {
+    jobs: HashMap<String, Model<Job>>,
+    workflow: Workflow,
+    succeeded: HashMap<String, DateTime<Utc>>, // job key -> last successful completion
+    triggered: HashMap<String, DateTime<Utc>>, // job key -> when its dependencies last fired it
+}
+
+/// Input - what the runner loop handles: job changes, a reload after missed changes, completed runs
+#[derive(Debug)]
+enum Input {
+    Event(JobEvent),
+    Reload,
+    Completed(Model<Job>),
+}
+
+/// Triggered - what a completed run sets off: dependents to fire and downstream jobs to block
+#[derive(Debug, Default, PartialEq)]
+pub struct Triggered {
+    pub fire: Vec<Model<Job>>,
+    pub block: Vec<Model<Job>>,
+}
+
+impl WorkflowRunner {
+    /// create an empty runner
+    pub fn new() -> WorkflowRunner {
+        WorkflowRunner::default()
+    }
+
+    /// add or replace the job; if its dependencies make a cycle, or it also has a `run_at`
+    /// schedule, the job is kept without them and the error is returned
+    pub fn upsert(&mut self, mut model: Model<Job>) -> Result<()> {
+        let key = model.key.to_string();
+        if model.value.run_at.is_some() && !model.value.depends_on.is_empty() {
+            model.value.depends_on.clear();
+            self.jobs.insert(key, model);
+            self.workflow = Workflow::new(self.jobs.values());
+
+            return Err(anyhow!(
+                "a job with dependencies is run by them and can't have a run_at schedule"
+            ));
+        }
+
+        self.jobs.insert(key.to_string(), model);
+
+        let workflow = Workflow::new(self.jobs.values());
+        match workflow.order() {
+            Ok(_) => {
+                self.workflow = workflow;
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(model) = self.jobs.get_mut(&key) {
+                    model.value.depends_on.clear();
+                }
+                self.workflow = Workflow::new(self.jobs.values());
+
+                Err(e)
+            }
+        }
+    }
+
+    /// remove the job
+    pub fn remove(&mut self, key: &str) {
+        self.jobs.remove(key);
+        self.succeeded.remove(key);
+        self.triggered.remove(key);
+        self.workflow = Workflow::new(self.jobs.values());
+    }
+
+    /// record the completed run and return the jobs it triggers
+    pub fn completed(&mut self, model: &Model<Job>, at: DateTime<Utc>) -> Triggered {
+        let mut triggered = Triggered::default();
+        let key = model.key.as_str();
+        if let Some(job) = self.jobs.get_mut(key) {
+            job.status = model.status.clone();
+        }
+
+        match succeeded(&model.status) {
+            Some(true) => {
+                self.succeeded.insert(key.to_string(), at);
+                for dependent in self.workflow.dependents(key).to_vec() {
+                    if self.is_ready(&dependent) && !self.is_blocked(&dependent) {
+                        self.triggered.insert(dependent.to_string(), at);
+                        if let Some(job) = self.jobs.get(&dependent) {
+                            triggered.fire.push(job.clone());
+                        }
+                    }
+                }
+            }
+            Some(false) => {
+                self.succeeded.remove(key);
+                for downstream in self.workflow.downstream(key) {
+                    self.succeeded.remove(&downstream);
+                    if let Some(job) = self.jobs.get(&downstream) {
+                        triggered.block.push(job.clone());
+                    }
+                }
+            }
+            None => (),
+        }
+
+        triggered
+    }
+
+    // ready when every dependency has succeeded since the job was last triggered
+    fn is_ready(&self, key: &str) -> bool {
+        let last = self.triggered.get(key);
+        self.workflow.dependencies(key).iter().all(|dependency| {
+            self.succeeded
+                .get(dependency)
+                .map_or(false, |at| last.map_or(true, |last| at > last))
+        })
+    }
+
+    // blocked for a reason other than a failed upstream job, which its success clears
+    fn is_blocked(&self, key: &str) -> bool {
+        self.jobs.get(key).map_or(
+            false,
+            |job| matches!(job.status, Status::Blocked(code) if code != BLOCKED_UPSTREAM_FAILED),
+        )
+    }
+
+    /// start the runner loop: read the runs the executor completed from `completions` and send
+    /// the dependent jobs to fire to `fire_tx`
+    pub fn start(
+        store: &JobStore,
+        completions: mpsc::Receiver<Model<Job>>,
+        fire_tx: mpsc::Sender<Model<Job>>,
+    ) -> JoinHandle<()> {
+        // subscribe before the initial scan so no changes are missed
+        let events = store.subscribe();
+        let request_channel = store.request_channel();
+        let (input_tx, mut inputs) = mpsc::channel(64);
+        forward_events(events, input_tx.clone());
+        forward_completions(completions, input_tx);
+
+        tokio::spawn(async move {
+            let mut runner = WorkflowRunner::new();
+            runner.load(&request_channel).await;
+
+            while let Some(input) = inputs.recv().await {
+                let event = match input {
+                    Input::Event(event) => event,
+                    Input::Reload => {
+                        runner.load(&request_channel).await;
+                        continue;
+                    }
+                    Input::Completed(model) => {
+                        if !runner.fire(&request_channel, &fire_tx, &model).await {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
+                let model = match event.model {
+                    Some(model) => model,
+                    None => continue,
+                };
+
+                match event.kind {
+                    EventKind::Inserted | EventKind::Updated => {
+                        if let Err(e) = runner.upsert(model.clone()) {
+                            // the blocked update comes back as an event, only block once
+                            if model.status != Status::Blocked(BLOCKED_INVALID_DEPENDENCIES) {
+                                let message = format!("blocked: {}", e);
+                                block(
+                                    &request_channel,
+                                    &model.key,
+                                    BLOCKED_INVALID_DEPENDENCIES,
+                                    &message,
+                                )
+                                .await;
+                            }
+                        }
+                    }
+                    EventKind::Removed => runner.remove(&model.key),
+                    _ => (),
+                }
+            }
+
+            info!("job store closed, stopping the workflow runner");
+        })
+    }
+
+    /// record the completed run, fire the dependents it makes ready and block the jobs downstream
+    /// of a failure; false if the fire channel is closed
+    async fn fire(
+        &mut self,
+        request_channel: &mpsc::Sender<Command>,
+        fire_tx: &mpsc::Sender<Model<Job>>,
+        model: &Model<Job>,
+    ) -> bool {
+        let triggered = self.completed(model, Utc::now());
+        for dependent in triggered.fire {
+            info!(
+                "job {} completed, firing dependent job {}",
+                model.key, dependent.key
+            );
+            if fire_tx.send(dependent).await.is_err() {
+                error!("fire channel closed, stopping the workflow runner");
+                return false;
+            }
+        }
+
+        let message = format!("blocked: upstream job {} failed", model.key);
+        for downstream in triggered.block {
+            block(
+                request_channel,
+                &downstream.key,
+                BLOCKED_UPSTREAM_FAILED,
+                &message,
+            )
+            .await;
+        }
+
+        true
+    }
+
+    /// rebuild the graph from every job in the store
+    async fn load(&mut self, request_channel: &mpsc::Sender<Command>) {
+        let (tx, rx) = oneshot::channel();
+        if request_channel
+            .send(Command::List(0, usize::MAX, tx))
+            .await
+            .is_err()
+        {
+            error!("could not list jobs from the store");
+            return;
+        }
+
+        self.jobs.clear();
+        for model in rx.await.unwrap_or_default() {
+            let key = model.key.to_string();
+            if let Err(e) = self.upsert(model) {
+                error!("job {}: {}", key, e);
+            }
+        }
+
+        info!("workflow runner loaded {} jobs", self.jobs.len());
+    }
+}
+
+/// pass the store's events to the runner loop, asking for a reload when events were missed
+fn forward_events(mut events: broadcast::Receiver<JobEvent>, inputs: mpsc::Sender<Input>) {
+    tokio::spawn(async move {
+        loop {
+            let input = match events.recv().await {
+                Ok(event) => Input::Event(event),
+                Err(RecvError::Lagged(count)) => {
+                    warn!("workflow runner missed {} job events, reloading", count);
+                    Input::Reload
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            if inputs.send(input).await.is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// pass the executor's completed runs to the runner loop
+fn forward_completions(mut completions: mpsc::Receiver<Model<Job>>, inputs: mpsc::Sender<Input>) {
+    tokio::spawn(async move {
+        while let Some(model) = completions.recv().await {
+            if inputs.send(Input::Completed(model)).await.is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Some(true) for a successful final status, Some(false) for a failure, None if not finished
+pub fn succeeded(status: &Status) -> Option<bool> {
+    match status {
+        Status::Processed(code) => Some(*code < 128),
+        Status::Blocked(_) => Some(false),
+        _ => None,
+    }
+}
+
+/// mark the job Blocked with the message in its errors; the store changes only those fields
+async fn block(request_channel: &mpsc::Sender<Command>, key: &str, code: u8, message: &str) {
+    warn!("job {} {}", key, message);
+
+    let (tx, rx) = oneshot::channel();
+    let cmd = Command::Block(key.to_string(), code, message.to_string(), tx);
+    if request_channel.send(cmd).await.is_err() || rx.await.is_err() {
+        error!("could not block job {}", key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::run_at::RunAt;
+    use crate::models::schedule::Schedule;
+
+    fn model(key: &str, depends_on: &[&str]) -> Model<Job> {
+        let mut model = Job::create_model(&Job::new(key, "no-op"));
+        model.key = key.to_string();
+        model.value.depends_on = depends_on.iter().map(|k| k.to_string()).collect();
+        model
+    }
+
+    fn done(model: &Model<Job>, code: u8) -> Model<Job> {
+        Job::update_model(model, Status::Processed(code))
+    }
+
+    fn keys(list: &[Model<Job>]) -> Vec<&str> {
+        list.iter().map(|model| model.key.as_str()).collect()
+    }
+
+    #[test]
+    fn fan_out_fan_in() {
+        let mut runner = WorkflowRunner::new();
+        let extract = model("extract", &[]);
+        let clean = model("clean", &["extract"]);
+        let stats = model("stats", &["extract"]);
+        let load = model("load", &["clean", "stats"]);
+
+        // dependencies may be added before the jobs they name
+        for job in [&load, &clean, &stats, &extract] {
+            runner.upsert(job.clone()).unwrap();
+        }
+
+        let now = Utc::now();
+        let triggered = runner.completed(&done(&extract, 0), now);
+        assert_eq!(keys(&triggered.fire), vec!["clean", "stats"]);
+
+        // fan in: load waits for both
+        let triggered = runner.completed(&done(&clean, 0), now);
+        assert!(triggered.fire.is_empty());
+        let triggered = runner.completed(&done(&stats, 0), now);
+        assert_eq!(keys(&triggered.fire), vec!["load"]);
+
+        // the next cycle needs both again
+        let later = now + chrono::Duration::minutes(5);
+        assert!(runner.completed(&done(&clean, 0), later).fire.is_empty());
+        assert_eq!(
+            keys(&runner.completed(&done(&stats, 0), later).fire),
+            vec!["load"]
+        );
+
+        // a failure blocks everything downstream
+        let triggered = runner.completed(&done(&extract, 131), later);
+        assert!(triggered.fire.is_empty());
+        assert_eq!(keys(&triggered.block), vec!["clean", "stats", "load"]);
+
+        // still running
+        let running = Job::update_model(&extract, Status::Active(0));
+        assert_eq!(runner.completed(&running, later), Triggered::default());
+    }
+
+    #[test]
+    fn cycle() {
+        let mut runner = WorkflowRunner::new();
+        runner.upsert(model("a", &["b"])).unwrap();
+        assert!(runner.upsert(model("b", &["a"])).is_err());
+
+        // b is kept without its dependencies, so a still runs after b
+        let triggered = runner.completed(&done(&model("b", &[]), 0), Utc::now());
+        assert_eq!(keys(&triggered.fire), vec!["a"]);
+    }
+
+    #[test]
+    fn blocked_dependents() {
+        let mut runner = WorkflowRunner::new();
+        let first = model("first", &[]);
+        runner.upsert(first.clone()).unwrap();
+
+        // a dependent can't also be scheduled
+        let mut scheduled = model("scheduled", &["first"]);
+        scheduled.value.run_at = Some(Schedule::Cron(RunAt::with_minutes(&vec![0u8])));
+        assert!(runner.upsert(scheduled).is_err());
+
+        // blocked by a failed upstream job, the next success fires it again
+        let second = model("second", &["first"]);
+        runner
+            .upsert(Job::update_model(
+                &second,
+                Status::Blocked(BLOCKED_UPSTREAM_FAILED),
+            ))
+            .unwrap();
+        let triggered = runner.completed(&done(&first, 0), Utc::now());
+        assert_eq!(keys(&triggered.fire), vec!["second"]);
+
+        // out of retries, it stays blocked until it's resolved
+        runner
+            .upsert(Job::update_model(&second, Status::Blocked(131)))
+            .unwrap();
+        let later = Utc::now() + chrono::Duration::minutes(5);
+        assert!(runner.completed(&done(&first, 0), later).fire.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fires_dependents() {
+        let store = JobStore::new().await;
+        let (fire_tx, mut fire_rx) = mpsc::channel(8);
+        let (completed_tx, completed_rx) = mpsc::channel(8);
+        let handle = WorkflowRunner::start(&store, completed_rx, fire_tx);
+        let channel = store.request_channel();
+
+        let first = Job::create_model(&Job::new("first", "no-op"));
+        let mut second = Job::new("second", "no-op");
+        second.depends_on = vec![first.key.to_string()];
+        let second = Job::create_model(&second);
+
+        for model in [&first, &second] {
+            let (tx, rx) = oneshot::channel();
+            channel
+                .send(Command::Insert(Box::new(model.clone()), tx))
+                .await
+                .unwrap();
+            rx.await.unwrap().unwrap();
+        }
+
+        // flood the broadcast so the runner lags; the completion still arrives
+        for _ in 0..200 {
+            let event = JobEvent::new(EventKind::Progress, "progress: 1", Some(first.clone()));
+            channel
+                .send(Command::Notify(Box::new(event)))
+                .await
+                .unwrap();
+        }
+        completed_tx.send(done(&first, 0)).await.unwrap();
+
+        let fired = tokio::time::timeout(std::time::Duration::from_secs(5), fire_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(fired.key, second.key);
+
+        handle.abort();
+    }
+}